@@ -0,0 +1,389 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Congruence closure on top of `UnificationTable`.
+//!
+//! In addition to plain keys (created with `new_key`), clients can
+//! register *applications* `f(a, b, ...)` of some function symbol `f`
+//! to a list of keys (created with `new_app`). Each application is
+//! itself a key, so it can be unified with other keys. Whenever two
+//! keys are unioned, any applications that thereby become
+//! *congruent* -- same function symbol, pairwise unioned arguments --
+//! are unioned as well, and so on transitively.
+//!
+//! This is the classic signature-table algorithm: each application is
+//! indexed by its *signature*, i.e. its function symbol applied to
+//! the roots of its arguments, and each root keeps a *use list* of
+//! the applications that mention a member of its class. When two
+//! classes merge, only the applications on the use list of the
+//! redirected root need to be re-indexed.
+//!
+//! Like `UnificationTable`, a `CongruenceClosure` can be snapshotted;
+//! rolling back undoes both the unifications and the bookkeeping of
+//! the congruence closure.
+
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use core::hash::Hash;
+use hashbrown::HashMap;
+
+use undo_log::{self, Rollback, Snapshots, UndoLogs, VecLog};
+use unify::{self, InPlace, InPlaceUnificationTable, NoError, UnifyIndex, UnifyKey, UnifyValue};
+
+#[cfg(test)]
+mod tests;
+
+/// A function symbol applied to a list of keys.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Application<K, F> {
+    func: F,
+    args: Vec<K>,
+}
+
+/// A congruence closure over keys of type `K`, where applications use
+/// function symbols of type `F`.
+#[derive(Debug)]
+pub struct CongruenceClosure<K: UnifyKey, F> {
+    table: InPlaceUnificationTable<K>,
+    state: ClosureState<K, F>,
+    undo_log: VecLog<UndoLog<K, F>>,
+}
+
+#[derive(Debug)]
+struct ClosureState<K, F> {
+    /// For each key, the application it was created for, if any.
+    apps: Vec<Option<Application<K, F>>>,
+
+    /// For each key, the signature under which it is currently
+    /// indexed in `signatures`. Applications that turned out to be
+    /// congruent to an already indexed application are not indexed.
+    indexed: Vec<Option<Application<K, F>>>,
+
+    /// For each root, the applications with an argument in its class.
+    /// The lists of keys that are no longer roots are left stale.
+    uses: Vec<Vec<K>>,
+
+    /// Maps the signature of each application (its function symbol
+    /// applied to the roots of its arguments) to that application.
+    signatures: HashMap<Application<K, F>, K>,
+}
+
+#[derive(Debug)]
+enum UndoLog<K, F> {
+    /// A new key was pushed.
+    NewKey,
+
+    /// An application was pushed onto the use list of the given root.
+    AddedUse(K),
+
+    /// The use list of the given root was extended from the given length.
+    ExtendedUses(K, usize),
+
+    /// The given application was indexed under a new signature.
+    Indexed(K),
+
+    /// The given application was removed from the index, where it had
+    /// the given signature.
+    Unindexed(K, Application<K, F>),
+}
+
+/// At any time, users may snapshot a congruence closure. The changes
+/// made during the snapshot may either be *committed* or *rolled back*.
+#[must_use = "snapshots must be committed or rolled back"]
+pub struct Snapshot<K: UnifyKey> {
+    table: unify::Snapshot<InPlace<K>>,
+    undo: undo_log::Snapshot,
+}
+
+impl<K, F> Rollback<UndoLog<K, F>> for ClosureState<K, F>
+where
+    K: UnifyKey + Eq + Hash,
+    F: Clone + Eq + Hash,
+{
+    fn reverse(&mut self, undo: UndoLog<K, F>) {
+        match undo {
+            UndoLog::NewKey => {
+                self.apps.pop();
+                self.indexed.pop();
+                self.uses.pop();
+            }
+            UndoLog::AddedUse(root) => {
+                self.uses[root.index().as_usize()].pop();
+            }
+            UndoLog::ExtendedUses(root, len) => {
+                self.uses[root.index().as_usize()].truncate(len);
+            }
+            UndoLog::Indexed(key) => {
+                let signature = self.indexed[key.index().as_usize()].take().unwrap();
+                self.signatures.remove(&signature);
+            }
+            UndoLog::Unindexed(key, signature) => {
+                self.signatures.insert(signature.clone(), key);
+                self.indexed[key.index().as_usize()] = Some(signature);
+            }
+        }
+    }
+}
+
+impl<K, F> Default for CongruenceClosure<K, F>
+where
+    K: UnifyKey + Eq + Hash,
+    F: Clone + Eq + Hash + Debug,
+{
+    fn default() -> Self {
+        CongruenceClosure {
+            table: InPlaceUnificationTable::new(),
+            state: ClosureState {
+                apps: Vec::new(),
+                indexed: Vec::new(),
+                uses: Vec::new(),
+                signatures: HashMap::new(),
+            },
+            undo_log: VecLog::default(),
+        }
+    }
+}
+
+impl<K, F> CongruenceClosure<K, F>
+where
+    K: UnifyKey + Eq + Hash,
+    F: Clone + Eq + Hash + Debug,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the underlying unification table.
+    pub fn table(&self) -> &InPlaceUnificationTable<K> {
+        &self.table
+    }
+
+    /// Returns the number of keys (including applications) created so far.
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    /// Returns true if no keys have been created yet.
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+
+    /// Creates a fresh key with the given value.
+    pub fn new_key(&mut self, value: K::Value) -> K {
+        self.push_key(value, None)
+    }
+
+    /// Returns the key for the application of `func` to `args`. If an
+    /// application congruent to this one already exists, its key is
+    /// returned; otherwise, a fresh key is created with a default value.
+    pub fn new_app(&mut self, func: F, args: &[K]) -> K
+    where
+        K::Value: Default,
+    {
+        let signature = self.signature(&func, args);
+        if let Some(&key) = self.state.signatures.get(&signature) {
+            return key;
+        }
+
+        let app = Application {
+            func,
+            args: args.to_vec(),
+        };
+        let key = self.push_key(K::Value::default(), Some(app));
+        debug!("{}: created new application: {:?}", K::tag(), key);
+
+        let mut roots = signature.args.clone();
+        roots.sort_by_key(|root| root.index());
+        roots.dedup();
+        for root in roots {
+            self.state.uses[root.index().as_usize()].push(key);
+            self.log(UndoLog::AddedUse(root));
+        }
+        self.index(key, signature);
+
+        key
+    }
+
+    /// If `key` was created by `new_app`, returns the function symbol
+    /// and the arguments it was created with.
+    pub fn application(&self, key: K) -> Option<(&F, &[K])> {
+        self.state.apps[key.index().as_usize()]
+            .as_ref()
+            .map(|app| (&app.func, &app.args[..]))
+    }
+
+    /// Given a key, returns the (current) root key.
+    pub fn find(&mut self, key: K) -> K {
+        self.table.find(key)
+    }
+
+    /// Given two keys, indicates whether they have been unioned together.
+    pub fn unioned(&mut self, a: K, b: K) -> bool {
+        self.table.unioned(a, b)
+    }
+
+    /// Returns the current value for the given key.
+    pub fn probe_value(&mut self, key: K) -> K::Value {
+        self.table.probe_value(key)
+    }
+
+    /// Sets the value of `key`, attempting to merge with the previous
+    /// value. Values play no part in congruence.
+    pub fn unify_var_value(
+        &mut self,
+        key: K,
+        value: K::Value,
+    ) -> Result<(), <K::Value as UnifyValue>::Error> {
+        self.table.unify_var_value(key, value)
+    }
+
+    /// Unions two keys without the possibility of failure; only
+    /// applicable when unify values use `NoError` as their error
+    /// type.
+    pub fn union(&mut self, a: K, b: K)
+    where
+        K::Value: UnifyValue<Error = NoError>,
+    {
+        self.unify_var_var(a, b).unwrap();
+    }
+
+    /// Unions together two keys, along with all applications that
+    /// become congruent as a result. If merging the values of any of
+    /// these keys fails, the error is propagated and this method has
+    /// no effect.
+    pub fn unify_var_var(&mut self, a: K, b: K) -> Result<(), <K::Value as UnifyValue>::Error> {
+        let snapshot = self.snapshot();
+        match self.merge(a, b) {
+            Ok(()) => {
+                self.commit(snapshot);
+                Ok(())
+            }
+            Err(err) => {
+                self.rollback_to(snapshot);
+                Err(err)
+            }
+        }
+    }
+
+    /// Starts a new snapshot. Each snapshot must be either
+    /// rolled back or committed in a "LIFO" (stack) order.
+    pub fn snapshot(&mut self) -> Snapshot<K> {
+        Snapshot {
+            table: self.table.snapshot(),
+            undo: self.undo_log.start_snapshot(),
+        }
+    }
+
+    /// Reverses all changes since the last snapshot. Also
+    /// removes any keys that have been created since then.
+    pub fn rollback_to(&mut self, snapshot: Snapshot<K>) {
+        debug!("{}: rollback_to()", K::tag());
+        self.table.rollback_to(snapshot.table);
+        let state = &mut self.state;
+        self.undo_log.rollback_to(|| state, snapshot.undo);
+    }
+
+    /// Commits all changes since the last snapshot. Of course, they
+    /// can still be undone if there is a snapshot further out.
+    pub fn commit(&mut self, snapshot: Snapshot<K>) {
+        debug!("{}: commit()", K::tag());
+        self.table.commit(snapshot.table);
+        self.undo_log.commit(snapshot.undo);
+    }
+
+    fn push_key(&mut self, value: K::Value, app: Option<Application<K, F>>) -> K {
+        let key = self.table.new_key(value);
+        self.state.apps.push(app);
+        self.state.indexed.push(None);
+        self.state.uses.push(Vec::new());
+        self.log(UndoLog::NewKey);
+        key
+    }
+
+    fn log(&mut self, undo: UndoLog<K, F>) {
+        if self.undo_log.in_snapshot() {
+            self.undo_log.push(undo);
+        }
+    }
+
+    /// Computes the signature of `func` applied to `args`.
+    fn signature(&mut self, func: &F, args: &[K]) -> Application<K, F> {
+        Application {
+            func: func.clone(),
+            args: args.iter().map(|&arg| self.table.find(arg)).collect(),
+        }
+    }
+
+    /// Computes the current signature of the application `key`.
+    fn signature_of(&mut self, key: K) -> Application<K, F> {
+        let app = self.state.apps[key.index().as_usize()]
+            .as_ref()
+            .expect("use lists only contain applications");
+        let table = &mut self.table;
+        Application {
+            func: app.func.clone(),
+            args: app.args.iter().map(|&arg| table.find(arg)).collect(),
+        }
+    }
+
+    fn index(&mut self, key: K, signature: Application<K, F>) {
+        self.state.signatures.insert(signature.clone(), key);
+        self.state.indexed[key.index().as_usize()] = Some(signature);
+        self.log(UndoLog::Indexed(key));
+    }
+
+    fn unindex(&mut self, key: K) {
+        if let Some(signature) = self.state.indexed[key.index().as_usize()].take() {
+            self.state.signatures.remove(&signature);
+            self.log(UndoLog::Unindexed(key, signature));
+        }
+    }
+
+    /// Unions `a` and `b` and propagates congruences. On error, the
+    /// table may be left partially updated; callers roll back.
+    fn merge(&mut self, a: K, b: K) -> Result<(), <K::Value as UnifyValue>::Error> {
+        let mut pending = vec![(a, b)];
+        while let Some((a, b)) = pending.pop() {
+            let root_a = self.table.find(a);
+            let root_b = self.table.find(b);
+            if root_a == root_b {
+                continue;
+            }
+
+            self.table.unify_var_var(root_a, root_b)?;
+            let new_root = self.table.find(root_a);
+            let old_root = if new_root == root_a { root_b } else { root_a };
+            debug!("{}: merged {:?} into {:?}", K::tag(), old_root, new_root);
+
+            // Only the signatures of applications with an argument in
+            // the redirected class can have changed. Re-index them; any
+            // collision with an existing signature is a new congruence.
+            let moved = self.state.uses[old_root.index().as_usize()].clone();
+            for &app in &moved {
+                let signature = self.signature_of(app);
+                if self.state.indexed[app.index().as_usize()].as_ref() == Some(&signature) {
+                    continue;
+                }
+                self.unindex(app);
+                let existing = self.state.signatures.get(&signature).cloned();
+                match existing {
+                    Some(other) => pending.push((app, other)),
+                    None => self.index(app, signature),
+                }
+            }
+
+            let uses = &mut self.state.uses[new_root.index().as_usize()];
+            let len = uses.len();
+            uses.extend(moved);
+            self.log(UndoLog::ExtendedUses(new_root, len));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use cc::CongruenceClosure;
+
+unify_key! {
+    struct TermKey {
+        value: (),
+        tag: "TermKey",
+    }
+
+    struct IntKey {
+        value: Option<i32>,
+        tag: "IntKey",
+    }
+}
+
+#[test]
+fn simple_congruence() {
+    let mut cc: CongruenceClosure<TermKey, &'static str> = CongruenceClosure::new();
+    let a = cc.new_key(());
+    let b = cc.new_key(());
+    let fa = cc.new_app("f", &[a]);
+    let fb = cc.new_app("f", &[b]);
+    let ga = cc.new_app("g", &[a]);
+    assert!(!cc.unioned(fa, fb));
+
+    cc.union(a, b);
+    assert!(cc.unioned(fa, fb));
+    assert!(!cc.unioned(fa, ga));
+}
+
+#[test]
+fn new_app_is_hash_consed() {
+    let mut cc: CongruenceClosure<TermKey, &'static str> = CongruenceClosure::new();
+    let a = cc.new_key(());
+    let b = cc.new_key(());
+    let fab = cc.new_app("f", &[a, b]);
+    assert_eq!(cc.new_app("f", &[a, b]), fab);
+    assert_eq!(cc.application(fab), Some((&"f", &[a, b][..])));
+    assert_eq!(cc.application(a), None);
+
+    cc.union(a, b);
+    assert_eq!(cc.new_app("f", &[b, a]), fab);
+}
+
+#[test]
+fn nested_congruence() {
+    // a = b implies g(f(a), a) = g(f(b), b).
+    let mut cc: CongruenceClosure<TermKey, &'static str> = CongruenceClosure::new();
+    let a = cc.new_key(());
+    let b = cc.new_key(());
+    let fa = cc.new_app("f", &[a]);
+    let fb = cc.new_app("f", &[b]);
+    let gfaa = cc.new_app("g", &[fa, a]);
+    let gfbb = cc.new_app("g", &[fb, b]);
+    let gfab = cc.new_app("g", &[fa, b]);
+
+    cc.union(a, b);
+    assert!(cc.unioned(gfaa, gfbb));
+    assert!(cc.unioned(gfaa, gfab));
+}
+
+#[test]
+fn congruence_after_argument_merge() {
+    // f(a) = c and a = b imply f(b) = c.
+    let mut cc: CongruenceClosure<TermKey, &'static str> = CongruenceClosure::new();
+    let a = cc.new_key(());
+    let b = cc.new_key(());
+    let c = cc.new_key(());
+    let fa = cc.new_app("f", &[a]);
+    let fb = cc.new_app("f", &[b]);
+    cc.union(fa, c);
+    cc.union(b, a);
+    assert!(cc.unioned(fb, c));
+}
+
+#[test]
+fn rollback_undoes_congruence() {
+    let mut cc: CongruenceClosure<TermKey, &'static str> = CongruenceClosure::new();
+    let a = cc.new_key(());
+    let b = cc.new_key(());
+    let fa = cc.new_app("f", &[a]);
+    let fb = cc.new_app("f", &[b]);
+
+    let snapshot = cc.snapshot();
+    cc.union(a, b);
+    let c = cc.new_key(());
+    let fc = cc.new_app("f", &[c]);
+    cc.union(c, a);
+    assert!(cc.unioned(fa, fb));
+    assert!(cc.unioned(fa, fc));
+    cc.rollback_to(snapshot);
+
+    assert_eq!(cc.len(), 4);
+    assert!(!cc.unioned(a, b));
+    assert!(!cc.unioned(fa, fb));
+
+    // The signature table must be back in its original state, too.
+    cc.union(a, b);
+    assert!(cc.unioned(fa, fb));
+    assert_eq!(cc.new_app("f", &[b]), cc.find(fa));
+}
+
+#[test]
+fn failed_union_has_no_effect() {
+    // Unifying a and b forces f(a) = f(b), whose values conflict.
+    let mut cc: CongruenceClosure<IntKey, &'static str> = CongruenceClosure::new();
+    let a = cc.new_key(None);
+    let b = cc.new_key(None);
+    let fa = cc.new_app("f", &[a]);
+    let fb = cc.new_app("f", &[b]);
+    assert!(cc.unify_var_value(fa, Some(1)).is_ok());
+    assert!(cc.unify_var_value(fb, Some(2)).is_ok());
+
+    assert!(cc.unify_var_var(a, b).is_err());
+    assert!(!cc.unioned(a, b));
+    assert!(!cc.unioned(fa, fb));
+    assert_eq!(cc.probe_value(fa), Some(1));
+    assert_eq!(cc.probe_value(fb), Some(2));
+}
@@ -0,0 +1,87 @@
+//! Errors reported by the `check_invariants` methods of unification
+//! tables and undo logs. These methods are meant to catch corruption
+//! early, e.g. by a user `Rollback` or `SnapshotVecDelegate::reverse`
+//! impl that does not exactly undo an action, so they report what is
+//! wrong instead of panicking.
+//!
+//! Keys are given by their index, since a corrupt table may refer to
+//! keys that do not exist.
+
+use core::fmt;
+#[cfg(feature = "std")]
+use std::error::Error;
+
+/// A violated invariant of a unification table or undo log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InvariantError {
+    /// The parent of `key` is not a key of the table.
+    ParentOutOfRange { key: usize, parent: usize },
+
+    /// The next member of the class of `key` is not a key of the table.
+    NextOutOfRange { key: usize, next: usize },
+
+    /// Following the parents of `key` never reaches a root.
+    Cycle { key: usize },
+
+    /// The weight (rank or size) of `key` is not smaller than that of
+    /// its parent. The strategies provided by this crate always make a
+    /// root heavier than the roots redirected to it.
+    WeightNotIncreasing { key: usize, parent: usize },
+
+    /// The circular list of members starting at `root` does not
+    /// consist of exactly the keys whose root is `root`.
+    BrokenMemberList { root: usize },
+
+    /// The table's count of classes does not match its roots.
+    WrongClassCount { counted: usize, roots: usize },
+
+    /// The undo log holds `len` actions although no snapshot is open.
+    LogWithoutSnapshot { len: usize },
+
+    /// The open snapshot at `depth` (0 being the outermost) was taken
+    /// when the undo log held `start` actions, which is before the
+    /// snapshot around it, past the end of the log, or, for the
+    /// outermost snapshot, not at the start of the log. This happens
+    /// when a snapshot is counted as open although it was closed.
+    SnapshotStartOutOfRange { depth: usize, start: usize },
+}
+
+impl fmt::Display for InvariantError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InvariantError::ParentOutOfRange { key, parent } => {
+                write!(f, "parent {} of key {} is out of range", parent, key)
+            }
+            InvariantError::NextOutOfRange { key, next } => {
+                write!(f, "next member {} of key {} is out of range", next, key)
+            }
+            InvariantError::Cycle { key } => write!(f, "the parents of key {} form a cycle", key),
+            InvariantError::WeightNotIncreasing { key, parent } => write!(
+                f,
+                "the weight of key {} is not smaller than that of its parent {}",
+                key, parent
+            ),
+            InvariantError::BrokenMemberList { root } => {
+                write!(f, "the member list of root {} is broken", root)
+            }
+            InvariantError::WrongClassCount { counted, roots } => write!(
+                f,
+                "the table counted {} classes but has {} roots",
+                counted, roots
+            ),
+            InvariantError::LogWithoutSnapshot { len } => write!(
+                f,
+                "the undo log holds {} actions but no snapshot is open",
+                len
+            ),
+            InvariantError::SnapshotStartOutOfRange { depth, start } => write!(
+                f,
+                "the snapshot at depth {} starts at action {}, which is out of range",
+                depth, start
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for InvariantError {}
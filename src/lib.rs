@@ -10,15 +10,45 @@
 
 //! An implementation of union-find. See the `unify` module for more
 //! details.
+//!
+//! The crate is `no_std` and only needs `alloc`; the `std` feature,
+//! enabled by default, adds `Error` impls and logging.
 
+#![no_std]
 #![cfg_attr(feature = "bench", feature(test))]
 
 #[macro_use]
+extern crate alloc;
+#[cfg(any(feature = "std", test))]
+extern crate std;
+
+extern crate hashbrown;
+#[cfg(feature = "log")]
 extern crate log;
 
 #[cfg(feature = "persistent")]
 extern crate dogged;
 
+#[cfg(feature = "serde")]
+extern crate serde;
+
+#[macro_use]
+mod macros;
+
+pub mod cc;
+pub mod invariants;
 pub mod snapshot_vec;
+#[cfg(feature = "testing")]
+pub mod testing;
 pub mod undo_log;
 pub mod unify;
+
+// Paths used by `unify_key!`, which must not assume that the calling
+// crate links `std`.
+#[doc(hidden)]
+pub mod __private {
+    pub use core::convert::From;
+    pub use core::fmt;
+    pub use core::option::Option;
+    pub use core::write;
+}
@@ -0,0 +1,135 @@
+/// Declares `Copy` newtypes around an integer index that implement `UnifyKey`.
+///
+/// Each key names its value type and the tag returned by
+/// `UnifyKey::tag`, and may name its index type (`u32` if omitted) and
+/// a function to use as `UnifyKey::order_roots`. Besides `UnifyKey`,
+/// the key implements `Clone`, `Copy`, `PartialEq`, `Eq`, `PartialOrd`,
+/// `Ord` and `Hash`, `Debug` (printing the tag and the index, e.g.
+/// `TyVid(3)`) and conversions from and to its index type.
+/// Attributes, including doc comments, are passed on to the struct.
+///
+/// ```
+/// #[macro_use]
+/// extern crate ena;
+///
+/// use ena::unify::{InPlaceUnificationTable, UnifyKey};
+///
+/// fn prefer_lower(a: IntVid, _: &(), b: IntVid, _: &()) -> Option<(IntVid, IntVid)> {
+///     Some(if a < b { (a, b) } else { (b, a) })
+/// }
+///
+/// unify_key! {
+///     /// A type variable.
+///     pub struct TyVid {
+///         value: (),
+///         tag: "TyVid",
+///     }
+///
+///     /// An integer variable, whose root is always the lowest key.
+///     pub struct IntVid {
+///         value: (),
+///         index: u16,
+///         tag: "IntVid",
+///         order_roots: prefer_lower,
+///     }
+/// }
+///
+/// fn main() {
+///     let mut table: InPlaceUnificationTable<IntVid> = InPlaceUnificationTable::new();
+///     let a = table.new_key(());
+///     let b = table.new_key(());
+///     table.union(b, a);
+///     assert_eq!(table.find(b), a);
+///     assert_eq!(format!("{:?}", b), "IntVid(1)");
+///     assert_eq!(TyVid::from(3).index(), 3);
+/// }
+/// ```
+#[macro_export]
+macro_rules! unify_key {
+    ($(
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident {
+            value: $value:ty,
+            $(index: $index:ty,)?
+            tag: $tag:expr
+            $(, order_roots: $order_roots:expr)?
+            $(,)?
+        }
+    )*) => {
+        $(
+            $(#[$attr])*
+            #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+            $vis struct $name($vis $crate::unify_key!(@index $($index)?));
+
+            impl $crate::unify::UnifyKey for $name {
+                type Value = $value;
+                type Index = $crate::unify_key!(@index $($index)?);
+
+                fn index(&self) -> Self::Index {
+                    self.0
+                }
+
+                fn from_index(u: Self::Index) -> $name {
+                    $name(u)
+                }
+
+                fn tag() -> &'static str {
+                    $tag
+                }
+
+                $(
+                    fn order_roots(
+                        a: $name,
+                        a_value: &$value,
+                        b: $name,
+                        b_value: &$value,
+                    ) -> $crate::__private::Option<($name, $name)> {
+                        $order_roots(a, a_value, b, b_value)
+                    }
+                )?
+            }
+
+            impl $crate::__private::fmt::Debug for $name {
+                fn fmt(
+                    &self,
+                    f: &mut $crate::__private::fmt::Formatter,
+                ) -> $crate::__private::fmt::Result {
+                    $crate::__private::write!(f, "{}({})", $tag, self.0)
+                }
+            }
+
+            impl $crate::__private::From<$crate::unify_key!(@index $($index)?)> for $name {
+                fn from(u: $crate::unify_key!(@index $($index)?)) -> $name {
+                    $name(u)
+                }
+            }
+
+            impl $crate::__private::From<$name> for $crate::unify_key!(@index $($index)?) {
+                fn from(key: $name) -> Self {
+                    key.0
+                }
+            }
+        )*
+    };
+
+    (@index) => { u32 };
+    (@index $index:ty) => { $index };
+}
+
+/// Logs through `log::debug!` if the `log` feature is enabled. Otherwise
+/// the arguments are only type-checked and the call compiles away.
+#[cfg(feature = "log")]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        ::log::debug!($($arg)*)
+    };
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = format_args!($($arg)*);
+        }
+    };
+}
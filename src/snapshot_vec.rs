@@ -21,12 +21,13 @@
 
 use self::UndoLog::*;
 
-use std::fmt;
-use std::marker::PhantomData;
-use std::mem;
-use std::ops;
+use alloc::vec::Vec;
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem;
+use core::ops;
 
-use undo_log::{Rollback, Snapshots, UndoLogs, VecLog};
+use undo_log::{DropBomb, Rollback, RollbackGuard, Snapshots, UndoLogs, VecLog};
 
 #[derive(Debug)]
 pub enum UndoLog<D: SnapshotVecDelegate> {
@@ -70,6 +71,9 @@ where
 {
     fn push(&mut self, item: D::Value);
     fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
     fn reserve(&mut self, size: usize);
 }
 
@@ -131,10 +135,14 @@ where
     }
 }
 
-// Snapshots are tokens that should be created/consumed linearly.
+// Snapshots are tokens that should be created/consumed linearly. In
+// debug builds with the `std` feature, dropping one without committing
+// or rolling it back panics.
+#[must_use = "snapshots must be committed or rolled back"]
 pub struct Snapshot<S = ::undo_log::Snapshot> {
     pub(crate) value_count: usize,
     snapshot: S,
+    bomb: DropBomb,
 }
 
 pub trait SnapshotVecDelegate {
@@ -165,10 +173,10 @@ impl<D: SnapshotVecDelegate, V: VecLike<D> + Default, L: Default> SnapshotVec<D,
 
 impl<D: SnapshotVecDelegate> SnapshotVecStorage<D> {
     /// Creates a `SnapshotVec` using the `undo_log`, allowing mutating methods to be called
-    pub fn with_log<'a, L>(
-        &'a mut self,
+    pub fn with_log<L>(
+        &mut self,
         undo_log: L,
-    ) -> SnapshotVec<D, &'a mut Vec<<D as SnapshotVecDelegate>::Value>, L>
+    ) -> SnapshotVec<D, &mut Vec<<D as SnapshotVecDelegate>::Value>, L>
     where
         L: UndoLogs<UndoLog<D>>,
     {
@@ -195,6 +203,10 @@ impl<V: VecLike<D>, D: SnapshotVecDelegate, U> SnapshotVec<D, V, U> {
         self.values.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.values.len() == 0
+    }
+
     pub fn get(&self, index: usize) -> &D::Value {
         &self.values.as_ref()[index]
     }
@@ -213,8 +225,51 @@ impl<V: VecLike<D>, D: SnapshotVecDelegate, U> SnapshotVec<D, V, U> {
     }
 }
 
+/// A `SnapshotVec` is serialized as the sequence of its values. Like
+/// its undo log, it can only be serialized while no snapshot is open.
+#[cfg(feature = "serde")]
+impl<D, V, L> serde::Serialize for SnapshotVec<D, V, L>
+where
+    D: SnapshotVecDelegate,
+    D::Value: serde::Serialize,
+    V: VecLike<D>,
+    L: UndoLogs<UndoLog<D>>,
+{
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.in_snapshot() {
+            return Err(serde::ser::Error::custom(
+                "cannot serialize a `SnapshotVec` with open snapshots",
+            ));
+        }
+        self.values.as_ref().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, D, V, L> serde::Deserialize<'de> for SnapshotVec<D, V, L>
+where
+    D: SnapshotVecDelegate,
+    D::Value: serde::Deserialize<'de>,
+    V: VecLike<D> + Default,
+    L: Default,
+{
+    fn deserialize<De: serde::Deserializer<'de>>(deserializer: De) -> Result<Self, De::Error> {
+        let elems = Vec::<D::Value>::deserialize(deserializer)?;
+        let mut values = V::default();
+        values.reserve(elems.len());
+        for elem in elems {
+            values.push(elem);
+        }
+        Ok(SnapshotVec {
+            values,
+            undo_log: L::default(),
+            _marker: PhantomData,
+        })
+    }
+}
+
 impl<V: VecLike<D>, D: SnapshotVecDelegate, L: UndoLogs<UndoLog<D>>> SnapshotVec<D, V, L> {
-    fn in_snapshot(&self) -> bool {
+    pub(crate) fn in_snapshot(&self) -> bool {
         self.undo_log.in_snapshot()
     }
 
@@ -281,6 +336,7 @@ where
         Snapshot {
             value_count: self.values.len(),
             snapshot: self.undo_log.start_snapshot(),
+            bomb: DropBomb::new(),
         }
     }
 
@@ -291,12 +347,57 @@ where
     pub fn rollback_to(&mut self, snapshot: Snapshot<L::Snapshot>) {
         let values = &mut self.values;
         self.undo_log.rollback_to(|| values, snapshot.snapshot);
+        snapshot.bomb.defuse();
     }
 
     /// Commits all changes since the last snapshot. Of course, they
     /// can still be undone if there is a snapshot further out.
     pub fn commit(&mut self, snapshot: Snapshot<L::Snapshot>) {
         self.undo_log.commit(snapshot.snapshot);
+        snapshot.bomb.defuse();
+    }
+
+    /// Runs `f` inside a new snapshot and then rolls back the changes
+    /// it made, returning the result of `f`. The changes are rolled
+    /// back even if `f` panics.
+    pub fn probe<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+        let snapshot = self.start_snapshot();
+        let mut guard =
+            RollbackGuard::new(self, snapshot, |vec, snapshot| vec.rollback_to(snapshot));
+        f(guard.target())
+    }
+
+    /// Runs `f` inside a new snapshot, which is committed if `f`
+    /// returns `Ok` and rolled back otherwise. The changes are rolled
+    /// back, too, if `f` panics.
+    pub fn commit_if_ok<R, E>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<R, E>,
+    ) -> Result<R, E> {
+        let snapshot = self.start_snapshot();
+        let mut guard =
+            RollbackGuard::new(self, snapshot, |vec, snapshot| vec.rollback_to(snapshot));
+        let result = f(guard.target());
+        if result.is_ok() {
+            guard.commit(|vec, snapshot| vec.commit(snapshot));
+        }
+        result
+    }
+}
+
+impl<D: SnapshotVecDelegate, V: VecLike<D>, L: UndoLogs<UndoLog<D>>> SnapshotVec<D, V, L> {
+    /// Checks the snapshot bookkeeping of the undo log; see
+    /// `UndoLogs::check_invariants`.
+    pub fn check_invariants(&self) -> Result<(), ::invariants::InvariantError> {
+        self.undo_log.check_invariants()
+    }
+}
+
+#[cfg(feature = "stats")]
+impl<D: SnapshotVecDelegate, V: VecLike<D>> SnapshotVec<D, V, VecLog<UndoLog<D>>> {
+    /// Returns the counters collected by the undo log.
+    pub fn undo_log_stats(&self) -> ::undo_log::UndoLogStats {
+        self.undo_log.stats()
     }
 }
 
@@ -439,3 +540,56 @@ fn nested_commit_then_rollback() {
     vec.rollback_to(snapshot1);
     assert_eq!(*vec.get(0), 22);
 }
+
+#[test]
+fn probe_and_commit_if_ok() {
+    let mut vec: SnapshotVec<i32> = SnapshotVec::default();
+    vec.push(22);
+    let len = vec.probe(|vec| {
+        vec.push(33);
+        vec.len()
+    });
+    assert_eq!(len, 2);
+    assert_eq!(vec.len(), 1);
+
+    let result: Result<(), ()> = vec.commit_if_ok(|vec| {
+        vec.set(0, 23);
+        Err(())
+    });
+    assert!(result.is_err());
+    assert_eq!(*vec.get(0), 22);
+
+    let result: Result<(), ()> = vec.commit_if_ok(|vec| {
+        vec.set(0, 24);
+        Ok(())
+    });
+    assert!(result.is_ok());
+    assert_eq!(*vec.get(0), 24);
+    assert!(!vec.in_snapshot());
+}
+
+#[test]
+fn probe_rolls_back_on_panic() {
+    use std::panic::{self, AssertUnwindSafe};
+
+    let mut vec: SnapshotVec<i32> = SnapshotVec::default();
+    vec.push(22);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        vec.commit_if_ok(|vec| -> Result<(), ()> {
+            vec.set(0, 23);
+            panic!("oops")
+        })
+    }));
+    assert!(result.is_err());
+    assert!(!vec.in_snapshot());
+    assert_eq!(*vec.get(0), 22);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "snapshot dropped without being committed or rolled back")]
+fn dropped_snapshot_panics() {
+    let mut vec: SnapshotVec<i32> = SnapshotVec::default();
+    vec.push(22);
+    let _ = vec.start_snapshot();
+}
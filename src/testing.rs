@@ -0,0 +1,444 @@
+//! Differential testing against naive reference models, for checking
+//! user `UnifyValue` and `SnapshotVecDelegate` impls (and this crate)
+//! on random sequences of operations. Requires the `testing` feature.
+//!
+//! `TableDriver` applies the same operations to a `UnificationTable`
+//! and to a `Model`, which keeps an explicit class id per key and
+//! implements snapshots by cloning itself, and panics as soon as the
+//! two disagree. `VecDriver` does the same for a `SnapshotVec` and a
+//! plain `Vec`. Runs are reproducible from their seed, which is part of
+//! every panic message.
+
+use alloc::vec::Vec;
+use core::fmt::Debug;
+
+use snapshot_vec::{SnapshotVec, SnapshotVecDelegate};
+use unify::{ByRank, Snapshot, UnificationStore, UnificationTable, UnifyKey, UnifyValue};
+use unify::{UnifyIndex, UnionStrategy};
+
+/// A small xorshift pseudo-random number generator, so that the
+/// drivers need no dependencies. Not suitable for anything but tests.
+#[derive(Clone, Debug)]
+pub struct XorShift(u64);
+
+impl XorShift {
+    /// Creates a generator from `seed`; equal seeds give equal
+    /// sequences.
+    pub fn new(seed: u64) -> Self {
+        // Zero is a fixed point of xorshift.
+        XorShift(seed ^ 0x9e37_79b9_7f4a_7c15)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Returns a number in `0..n`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is zero.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+/// A naive union-find: every key stores the id of its class, unions
+/// relabel one of the classes, and snapshots are full copies.
+#[derive(Clone, Debug)]
+pub struct Model<V> {
+    class_of: Vec<usize>,
+    values: Vec<V>,
+    snapshots: Vec<(Vec<usize>, Vec<V>)>,
+}
+
+impl<V> Default for Model<V> {
+    fn default() -> Self {
+        Model {
+            class_of: Vec::new(),
+            values: Vec::new(),
+            snapshots: Vec::new(),
+        }
+    }
+}
+
+impl<V: UnifyValue> Model<V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of keys.
+    pub fn len(&self) -> usize {
+        self.class_of.len()
+    }
+
+    /// Returns true if there are no keys.
+    pub fn is_empty(&self) -> bool {
+        self.class_of.is_empty()
+    }
+
+    /// Returns the number of classes.
+    pub fn num_classes(&self) -> usize {
+        (0..self.len())
+            .filter(|&key| self.class_of[key] == key)
+            .count()
+    }
+
+    /// Returns the number of open snapshots.
+    pub fn num_open_snapshots(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    /// Creates a key with the given value and returns its index.
+    pub fn new_key(&mut self, value: V) -> usize {
+        let key = self.len();
+        self.class_of.push(key);
+        self.values.push(value);
+        key
+    }
+
+    /// Returns true if the two keys are in the same class.
+    pub fn unioned(&self, a: usize, b: usize) -> bool {
+        self.class_of[a] == self.class_of[b]
+    }
+
+    /// Returns the value of the class of `key`.
+    pub fn probe_value(&self, key: usize) -> &V {
+        &self.values[self.class_of[key]]
+    }
+
+    /// Unions the classes of `a` and `b`, merging their values (those
+    /// of `a` first). On error, nothing changes.
+    pub fn unify_var_var(&mut self, a: usize, b: usize) -> Result<(), V::Error> {
+        let (class_a, class_b) = (self.class_of[a], self.class_of[b]);
+        if class_a == class_b {
+            return Ok(());
+        }
+        let value = V::unify_values(&self.values[class_a], &self.values[class_b])?;
+        for class in &mut self.class_of {
+            if *class == class_b {
+                *class = class_a;
+            }
+        }
+        self.values[class_a] = value;
+        Ok(())
+    }
+
+    /// Merges `value` into the value of the class of `key`. On error,
+    /// nothing changes.
+    pub fn unify_var_value(&mut self, key: usize, value: V) -> Result<(), V::Error> {
+        let class = self.class_of[key];
+        self.values[class] = V::unify_values(&self.values[class], &value)?;
+        Ok(())
+    }
+
+    pub fn snapshot(&mut self) {
+        let copy = (self.class_of.clone(), self.values.clone());
+        self.snapshots.push(copy);
+    }
+
+    /// Restores the state of the innermost open snapshot.
+    pub fn rollback(&mut self) {
+        let (class_of, values) = self.snapshots.pop().expect("no open snapshot");
+        self.class_of = class_of;
+        self.values = values;
+    }
+
+    /// Closes the innermost open snapshot, keeping the current state.
+    pub fn commit(&mut self) {
+        self.snapshots.pop().expect("no open snapshot");
+    }
+}
+
+/// An operation applied by `TableDriver`. Keys are given by index.
+#[derive(Clone, Debug)]
+pub enum Op<V> {
+    NewKey(V),
+    UnifyVarVar(usize, usize),
+    UnifyVarValue(usize, V),
+    Snapshot,
+    /// Rolls back the innermost open snapshot.
+    RollbackTo,
+    /// Commits the innermost open snapshot.
+    Commit,
+}
+
+/// Applies operations to a `UnificationTable` with store `S` and
+/// strategy `U` and to a `Model`, checking after each one that they
+/// agree on the classes, the values and the number of keys and
+/// classes. Snapshots that are still open when the driver is dropped
+/// are rolled back.
+pub struct TableDriver<S: UnificationStore, U = ByRank> {
+    table: UnificationTable<S, U>,
+    model: Model<S::Value>,
+    snapshots: Vec<Snapshot<S>>,
+    seed: Option<u64>,
+    step: usize,
+}
+
+// A manual impl avoids a `Default` bound on `U`.
+impl<S: UnificationStore + Default, U> Default for TableDriver<S, U> {
+    fn default() -> Self {
+        TableDriver {
+            table: UnificationTable::default(),
+            model: Model::default(),
+            snapshots: Vec::new(),
+            seed: None,
+            step: 0,
+        }
+    }
+}
+
+impl<S, K, V, U> TableDriver<S, U>
+where
+    S: UnificationStore<Key = K, Value = V> + Default,
+    K: UnifyKey<Value = V>,
+    V: UnifyValue + PartialEq,
+    U: UnionStrategy,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the table under test.
+    pub fn table(&self) -> &UnificationTable<S, U> {
+        &self.table
+    }
+
+    /// Returns the reference model.
+    pub fn model(&self) -> &Model<V> {
+        &self.model
+    }
+
+    /// Applies `op` to both the table and the model and checks that
+    /// they agree afterwards. Operations that refer to a missing key or
+    /// snapshot are ignored.
+    pub fn apply(&mut self, op: Op<V>) {
+        let len = self.model.len();
+        match op {
+            Op::NewKey(value) => {
+                let key = self.table.new_key(value.clone());
+                let index = self.model.new_key(value);
+                self.expect(key.index().as_usize() == index, &"new keys differ");
+            }
+            Op::UnifyVarVar(a, b) if a < len && b < len => {
+                let table = self.table.unify_var_var(key::<K>(a), key::<K>(b)).is_ok();
+                let model = self.model.unify_var_var(a, b).is_ok();
+                self.expect(table == model, &format_args!("unifying {} with {}", a, b));
+            }
+            Op::UnifyVarValue(a, value) if a < len => {
+                let table = self
+                    .table
+                    .unify_var_value(key::<K>(a), value.clone())
+                    .is_ok();
+                let model = self.model.unify_var_value(a, value.clone()).is_ok();
+                self.expect(
+                    table == model,
+                    &format_args!("unifying {} with {:?}", a, value),
+                );
+            }
+            Op::Snapshot => {
+                self.snapshots.push(self.table.snapshot());
+                self.model.snapshot();
+            }
+            Op::RollbackTo if !self.snapshots.is_empty() => {
+                let snapshot = self.snapshots.pop().unwrap();
+                self.table.rollback_to(snapshot);
+                self.model.rollback();
+            }
+            Op::Commit if !self.snapshots.is_empty() => {
+                let snapshot = self.snapshots.pop().unwrap();
+                self.table.commit(snapshot);
+                self.model.commit();
+            }
+            _ => {}
+        }
+        self.step += 1;
+        self.check();
+    }
+
+    /// Checks that the table and the model agree.
+    pub fn check(&mut self) {
+        let len = self.model.len();
+        self.expect(self.table.len() == len, &"lengths differ");
+        self.expect(
+            self.table.num_classes() == self.model.num_classes(),
+            &"numbers of classes differ",
+        );
+        if let Err(error) = self.table.check_invariants() {
+            self.expect(false, &error);
+        }
+
+        // The roots of the table must correspond one to one to the
+        // classes of the model.
+        let mut class_of_root = vec![None; len];
+        for a in 0..len {
+            let root = self.table.find(key::<K>(a)).index().as_usize();
+            let class = self.model.class_of[a];
+            let agrees = *class_of_root[root].get_or_insert(class) == class;
+            self.expect(agrees, &format_args!("the class of {} differs", a));
+            let value = self.table.probe_value(key::<K>(a));
+            let agrees = value == *self.model.probe_value(a);
+            self.expect(agrees, &format_args!("the value of {} differs", a));
+        }
+    }
+
+    /// Applies `steps` random operations, drawing the values of new
+    /// keys and of `unify_var_value` from `value`, and then closes all
+    /// open snapshots. The same seed always yields the same operations.
+    pub fn run_random(
+        &mut self,
+        seed: u64,
+        steps: usize,
+        mut value: impl FnMut(&mut XorShift) -> V,
+    ) {
+        let mut rng = XorShift::new(seed);
+        self.seed = Some(seed);
+        self.step = 0;
+        for _ in 0..steps {
+            let len = self.model.len();
+            let op = match rng.below(20) {
+                _ if len == 0 => Op::NewKey(value(&mut rng)),
+                0..=3 => Op::NewKey(value(&mut rng)),
+                4..=10 => Op::UnifyVarVar(rng.below(len), rng.below(len)),
+                11..=13 => Op::UnifyVarValue(rng.below(len), value(&mut rng)),
+                14..=15 => Op::Snapshot,
+                16..=17 => Op::RollbackTo,
+                _ => Op::Commit,
+            };
+            self.apply(op);
+        }
+        while !self.snapshots.is_empty() {
+            self.apply(Op::Commit);
+        }
+    }
+
+    fn expect(&self, condition: bool, what: &dyn Debug) {
+        if !condition {
+            match self.seed {
+                Some(seed) => panic!(
+                    "table and model disagree at step {} of seed {}: {:?}",
+                    self.step, seed, what
+                ),
+                None => panic!("table and model disagree at step {}: {:?}", self.step, what),
+            }
+        }
+    }
+}
+
+impl<S: UnificationStore, U> Drop for TableDriver<S, U> {
+    fn drop(&mut self) {
+        while let Some(snapshot) = self.snapshots.pop() {
+            self.table.rollback_to(snapshot);
+        }
+    }
+}
+
+fn key<K: UnifyKey>(index: usize) -> K {
+    K::from_index(K::Index::from_usize(index))
+}
+
+/// Applies random operations to a `SnapshotVec` and to a plain `Vec`
+/// with a stack of copies as snapshots, checking after each one that
+/// they hold the same values.
+pub struct VecDriver<D: SnapshotVecDelegate> {
+    vec: SnapshotVec<D>,
+    model: Vec<D::Value>,
+    model_snapshots: Vec<Vec<D::Value>>,
+    snapshots: Vec<::snapshot_vec::Snapshot>,
+}
+
+// A manual impl avoids a `Default` bound on `D`.
+impl<D: SnapshotVecDelegate> Default for VecDriver<D> {
+    fn default() -> Self {
+        VecDriver {
+            vec: SnapshotVec::new(),
+            model: Vec::new(),
+            model_snapshots: Vec::new(),
+            snapshots: Vec::new(),
+        }
+    }
+}
+
+impl<D> VecDriver<D>
+where
+    D: SnapshotVecDelegate,
+    D::Value: Clone + Debug + PartialEq,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Applies `steps` random pushes, sets, snapshots, rollbacks and
+    /// commits, drawing values from `value`, and then closes all open
+    /// snapshots. Some of the steps instead call `edit`, which must make
+    /// the same change to the `SnapshotVec` and to the model; use it to
+    /// exercise custom undo actions recorded with `SnapshotVec::record`.
+    pub fn run_random(
+        &mut self,
+        seed: u64,
+        steps: usize,
+        mut value: impl FnMut(&mut XorShift) -> D::Value,
+        mut edit: impl FnMut(&mut XorShift, &mut SnapshotVec<D>, &mut Vec<D::Value>),
+    ) {
+        let mut rng = XorShift::new(seed);
+        for step in 0..steps {
+            let len = self.model.len();
+            match rng.below(10) {
+                _ if len == 0 => {
+                    let value = value(&mut rng);
+                    self.vec.push(value.clone());
+                    self.model.push(value);
+                }
+                0..=2 => {
+                    let value = value(&mut rng);
+                    self.vec.push(value.clone());
+                    self.model.push(value);
+                }
+                3..=4 => {
+                    let (index, value) = (rng.below(len), value(&mut rng));
+                    self.vec.set(index, value.clone());
+                    self.model[index] = value;
+                }
+                5 => edit(&mut rng, &mut self.vec, &mut self.model),
+                6..=7 => {
+                    self.snapshots.push(self.vec.start_snapshot());
+                    self.model_snapshots.push(self.model.clone());
+                }
+                8 => {
+                    if let Some(snapshot) = self.snapshots.pop() {
+                        self.vec.rollback_to(snapshot);
+                        self.model = self.model_snapshots.pop().unwrap();
+                    }
+                }
+                _ => {
+                    if let Some(snapshot) = self.snapshots.pop() {
+                        self.vec.commit(snapshot);
+                        self.model_snapshots.pop();
+                    }
+                }
+            }
+            assert!(
+                *self.vec == self.model[..],
+                "vec and model disagree at step {} of seed {}",
+                step,
+                seed
+            );
+        }
+        while let Some(snapshot) = self.snapshots.pop() {
+            self.vec.commit(snapshot);
+            self.model_snapshots.pop();
+        }
+    }
+}
+
+impl<D: SnapshotVecDelegate> Drop for VecDriver<D> {
+    fn drop(&mut self) {
+        while let Some(snapshot) = self.snapshots.pop() {
+            self.vec.rollback_to(snapshot);
+        }
+    }
+}
@@ -8,6 +8,10 @@
 //! Since the `*Storage` variants do not have an undo log `with_log` must be called with the
 //! unified log before any mutating actions.
 
+use alloc::vec::Vec;
+
+use invariants::InvariantError;
+
 /// A trait which allows undo actions (`T`) to be pushed which can be used to rollback actions at a
 /// later time if needed.
 ///
@@ -30,6 +34,12 @@ pub trait UndoLogs<T> {
     /// Removes all items from the undo log.
     fn clear(&mut self);
 
+    /// Checks the snapshot bookkeeping of the undo log. Logs that keep
+    /// no such bookkeeping have nothing to check.
+    fn check_invariants(&self) -> Result<(), InvariantError> {
+        Ok(())
+    }
+
     /// Extends the undo log with many undos.
     fn extend<I>(&mut self, undos: I)
     where
@@ -40,7 +50,7 @@ pub trait UndoLogs<T> {
     }
 }
 
-impl<'a, T, U> UndoLogs<T> for &'a mut U
+impl<T, U> UndoLogs<T> for &'_ mut U
 where
     U: UndoLogs<T>,
 {
@@ -56,6 +66,9 @@ where
     fn clear(&mut self) {
         U::clear(self);
     }
+    fn check_invariants(&self) -> Result<(), InvariantError> {
+        U::check_invariants(self)
+    }
     fn extend<I>(&mut self, undos: I)
     where
         Self: Sized,
@@ -93,6 +106,96 @@ pub trait Snapshots<T>: UndoLogs<T> {
 
     /// Commit: keep the changes that have been made since the snapshot began
     fn commit(&mut self, snapshot: Self::Snapshot);
+
+    /// Runs `f` inside a new snapshot and then rolls back the changes it made to `storage`,
+    /// returning the result of `f`. The changes are rolled back even if `f` panics.
+    fn probe<R, U>(&mut self, storage: &mut R, f: impl FnOnce(&mut Self, &mut R) -> U) -> U
+    where
+        Self: Sized,
+        R: Rollback<T>,
+    {
+        let snapshot = self.start_snapshot();
+        let mut guard = RollbackGuard::new((self, storage), snapshot, |target, snapshot| {
+            let storage = &mut *target.1;
+            target.0.rollback_to(|| storage, snapshot)
+        });
+        let target = guard.target();
+        f(target.0, target.1)
+    }
+
+    /// Runs `f` inside a new snapshot, which is committed if `f` returns `Ok` and rolled back
+    /// otherwise. The changes are rolled back, too, if `f` panics.
+    fn commit_if_ok<R, U, E>(
+        &mut self,
+        storage: &mut R,
+        f: impl FnOnce(&mut Self, &mut R) -> Result<U, E>,
+    ) -> Result<U, E>
+    where
+        Self: Sized,
+        R: Rollback<T>,
+    {
+        let snapshot = self.start_snapshot();
+        let mut guard = RollbackGuard::new((self, storage), snapshot, |target, snapshot| {
+            let storage = &mut *target.1;
+            target.0.rollback_to(|| storage, snapshot)
+        });
+        let result = {
+            let target = guard.target();
+            f(target.0, target.1)
+        };
+        if result.is_ok() {
+            guard.commit(|target, snapshot| target.0.commit(snapshot));
+        }
+        result
+    }
+}
+
+/// Rolls `target` back to a snapshot when dropped, unless `commit` was called first. This makes
+/// the closure-based snapshot helpers panic-safe: if the closure unwinds, the snapshot is still
+/// closed and the number of open snapshots stays consistent.
+pub(crate) struct RollbackGuard<T, S> {
+    target: T,
+    snapshot: Option<S>,
+    rollback: fn(&mut T, S),
+}
+
+impl<T, S> RollbackGuard<T, S> {
+    pub(crate) fn new(target: T, snapshot: S, rollback: fn(&mut T, S)) -> Self {
+        RollbackGuard {
+            target,
+            snapshot: Some(snapshot),
+            rollback,
+        }
+    }
+
+    pub(crate) fn target(&mut self) -> &mut T {
+        &mut self.target
+    }
+
+    pub(crate) fn get(&self) -> &T {
+        &self.target
+    }
+
+    pub(crate) fn snapshot(&self) -> &S {
+        self.snapshot.as_ref().unwrap()
+    }
+
+    /// Rolls the snapshot back now rather than when the guard is dropped.
+    pub(crate) fn rollback(self) {}
+
+    /// Closes the snapshot with `commit` instead of rolling it back.
+    pub(crate) fn commit(mut self, commit: fn(&mut T, S)) {
+        let snapshot = self.snapshot.take().unwrap();
+        commit(&mut self.target, snapshot);
+    }
+}
+
+impl<T, S> Drop for RollbackGuard<T, S> {
+    fn drop(&mut self) {
+        if let Some(snapshot) = self.snapshot.take() {
+            (self.rollback)(&mut self.target, snapshot);
+        }
+    }
 }
 
 impl<T, U> Snapshots<T> for &'_ mut U
@@ -135,28 +238,74 @@ impl<T> UndoLogs<T> for NoUndo {
 #[derive(Clone, Debug)]
 pub struct VecLog<T> {
     log: Vec<T>,
-    num_open_snapshots: usize,
+    // Length of the log when each open snapshot was taken, outermost
+    // first.
+    snapshot_starts: Vec<usize>,
+    #[cfg(feature = "stats")]
+    stats: UndoLogStats,
+}
+
+/// Counters collected by a `VecLog` when the `stats` feature is
+/// enabled; see `VecLog::stats`.
+#[cfg(feature = "stats")]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UndoLogStats {
+    /// The largest number of actions the log has held at once.
+    pub peak_len: usize,
+    /// The number of snapshots that were rolled back.
+    pub rollbacks: u64,
+    /// The total number of actions reversed by rollbacks.
+    pub actions_rolled_back: u64,
 }
 
 impl<T> Default for VecLog<T> {
     fn default() -> Self {
         VecLog {
             log: Vec::new(),
-            num_open_snapshots: 0,
+            snapshot_starts: Vec::new(),
+            #[cfg(feature = "stats")]
+            stats: UndoLogStats::default(),
         }
     }
 }
 
 impl<T> UndoLogs<T> for VecLog<T> {
     fn num_open_snapshots(&self) -> usize {
-        self.num_open_snapshots
+        self.snapshot_starts.len()
     }
     fn push(&mut self, undo: T) {
         self.log.push(undo);
+        #[cfg(feature = "stats")]
+        {
+            self.stats.peak_len = self.stats.peak_len.max(self.log.len());
+        }
     }
     fn clear(&mut self) {
         self.log.clear();
-        self.num_open_snapshots = 0;
+        self.snapshot_starts.clear();
+    }
+
+    /// Checks that the log is empty while no snapshot is open: actions
+    /// are only recorded inside snapshots, and closing the outermost
+    /// snapshot empties the log. For the same reason the outermost open
+    /// snapshot must have been taken on an empty log, and each inner
+    /// one no earlier than the one around it and no later than the end
+    /// of the log.
+    fn check_invariants(&self) -> Result<(), InvariantError> {
+        if self.snapshot_starts.is_empty() && !self.log.is_empty() {
+            return Err(InvariantError::LogWithoutSnapshot {
+                len: self.log.len(),
+            });
+        }
+        let mut min_start = 0;
+        for (depth, &start) in self.snapshot_starts.iter().enumerate() {
+            let max_start = if depth == 0 { 0 } else { self.log.len() };
+            if start < min_start || start > max_start {
+                return Err(InvariantError::SnapshotStartOutOfRange { depth, start });
+            }
+            min_start = start;
+        }
+        Ok(())
     }
 }
 
@@ -171,9 +320,12 @@ impl<T> Snapshots<T> for VecLog<T> {
     }
 
     fn start_snapshot(&mut self) -> Snapshot {
-        self.num_open_snapshots += 1;
+        let depth = self.snapshot_starts.len();
+        self.snapshot_starts.push(self.log.len());
         Snapshot {
             undo_len: self.log.len(),
+            depth,
+            bomb: DropBomb::new(),
         }
     }
 
@@ -185,6 +337,12 @@ impl<T> Snapshots<T> for VecLog<T> {
 
         self.assert_open_snapshot(&snapshot);
 
+        #[cfg(feature = "stats")]
+        {
+            self.stats.rollbacks += 1;
+            self.stats.actions_rolled_back += (self.log.len() - snapshot.undo_len) as u64;
+        }
+
         if self.log.len() > snapshot.undo_len {
             let mut values = values();
             while self.log.len() > snapshot.undo_len {
@@ -192,7 +350,10 @@ impl<T> Snapshots<T> for VecLog<T> {
             }
         }
 
-        self.num_open_snapshots -= 1;
+        // Also closes any inner snapshots that were never closed, as
+        // happens when a panic unwinds through them.
+        self.snapshot_starts.truncate(snapshot.depth);
+        snapshot.bomb.defuse();
     }
 
     fn commit(&mut self, snapshot: Snapshot) {
@@ -200,7 +361,7 @@ impl<T> Snapshots<T> for VecLog<T> {
 
         self.assert_open_snapshot(&snapshot);
 
-        if self.num_open_snapshots == 1 {
+        if snapshot.depth == 0 {
             // The root snapshot. It's safe to clear the undo log because
             // there's no snapshot further out that we might need to roll back
             // to.
@@ -208,25 +369,59 @@ impl<T> Snapshots<T> for VecLog<T> {
             self.log.clear();
         }
 
-        self.num_open_snapshots -= 1;
+        self.snapshot_starts.truncate(snapshot.depth);
+        snapshot.bomb.defuse();
     }
 }
 
 impl<T> VecLog<T> {
+    /// Returns the counters collected so far. They are never reset,
+    /// not even by `clear` or a rollback.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> UndoLogStats {
+        self.stats.clone()
+    }
+
     fn assert_open_snapshot(&self, snapshot: &Snapshot) {
         // Failures here may indicate a failure to follow a stack discipline.
         assert!(self.log.len() >= snapshot.undo_len);
-        assert!(self.num_open_snapshots > 0);
+        assert_eq!(
+            self.snapshot_starts.get(snapshot.depth),
+            Some(&snapshot.undo_len)
+        );
     }
 }
 
-impl<T> std::ops::Index<usize> for VecLog<T> {
+impl<T> core::ops::Index<usize> for VecLog<T> {
     type Output = T;
     fn index(&self, key: usize) -> &T {
         &self.log[key]
     }
 }
 
+/// A `VecLog` can only be serialized while no snapshot is open. Its
+/// entries could never be rolled back at that point, so nothing is
+/// written and deserializing yields an empty log.
+#[cfg(feature = "serde")]
+impl<T> serde::Serialize for VecLog<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if !self.snapshot_starts.is_empty() {
+            return Err(serde::ser::Error::custom(
+                "cannot serialize a `VecLog` with open snapshots",
+            ));
+        }
+        serializer.serialize_unit()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T> serde::Deserialize<'de> for VecLog<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        <()>::deserialize(deserializer)?;
+        Ok(VecLog::default())
+    }
+}
+
 /// A trait implemented for storage types (like `SnapshotVecStorage`) which can be rolled back using actions of type `U`.
 pub trait Rollback<U> {
     fn reverse(&mut self, undo: U);
@@ -242,7 +437,83 @@ where
 }
 
 /// Snapshots are tokens that should be created/consumed linearly.
+/// In debug builds with the `std` feature, dropping one without
+/// passing it to `commit` or `rollback_to` panics.
+#[must_use = "snapshots must be committed or rolled back"]
 pub struct Snapshot {
     // Length of the undo log at the time the snapshot was taken.
     undo_len: usize,
+    // Number of snapshots that were open when this one was taken.
+    depth: usize,
+    bomb: DropBomb,
+}
+
+/// Embedded in snapshot tokens to catch tokens that are dropped instead
+/// of being committed or rolled back, which would leave the snapshot
+/// open forever. Dropping an armed bomb panics in debug builds, unless
+/// the thread is already panicking. Without the `std` feature there is
+/// no way to tell whether the thread is panicking, and panicking while
+/// unwinding aborts, so the check is skipped and dropping does nothing;
+/// the same holds in release builds.
+#[derive(Debug)]
+pub(crate) struct DropBomb(());
+
+impl DropBomb {
+    pub(crate) fn new() -> Self {
+        DropBomb(())
+    }
+
+    /// Disarms the bomb; called when the snapshot is consumed.
+    pub(crate) fn defuse(self) {
+        #[cfg(all(debug_assertions, any(feature = "std", test)))]
+        core::mem::forget(self);
+    }
+}
+
+#[cfg(all(debug_assertions, any(feature = "std", test)))]
+impl Drop for DropBomb {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            return;
+        }
+        panic!("snapshot dropped without being committed or rolled back");
+    }
+}
+
+#[test]
+fn check_invariants_catches_stale_snapshots() {
+    fn log_with(len: usize, snapshot_starts: Vec<usize>) -> VecLog<()> {
+        VecLog {
+            log: vec![(); len],
+            snapshot_starts,
+            #[cfg(feature = "stats")]
+            stats: UndoLogStats::default(),
+        }
+    }
+
+    // An outer snapshot was closed while an inner one, taken after the
+    // first action, was still counted as open.
+    let log = log_with(2, vec![1]);
+    assert!(log.in_snapshot());
+    assert_eq!(
+        log.check_invariants(),
+        Err(InvariantError::SnapshotStartOutOfRange { depth: 0, start: 1 })
+    );
+
+    // An inner snapshot outlived the rollback of the one around it.
+    let log = log_with(1, vec![0, 3]);
+    assert_eq!(
+        log.check_invariants(),
+        Err(InvariantError::SnapshotStartOutOfRange { depth: 1, start: 3 })
+    );
+
+    let mut log: VecLog<()> = VecLog::default();
+    let outer = log.start_snapshot();
+    log.push(());
+    let inner = log.start_snapshot();
+    log.push(());
+    assert_eq!(log.check_invariants(), Ok(()));
+    log.commit(inner);
+    log.commit(outer);
+    assert_eq!(log.check_invariants(), Ok(()));
 }
@@ -1,17 +1,23 @@
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::mem;
+use core::ops::{self, Range};
 #[cfg(feature = "persistent")]
 use dogged::DVec;
 use snapshot_vec as sv;
-use std::marker::PhantomData;
-use std::ops::{self, Range};
 
+use invariants::InvariantError;
 use undo_log::{Rollback, Snapshots, UndoLogs, VecLog};
 
-use super::{UnifyKey, UnifyValue, VarValue};
+use super::{PathCompression, UnifyIndex, UnifyKey, UnifyValue, VarValue};
 
 #[allow(dead_code)] // rustc BUG
 #[allow(type_alias_bounds)]
 type Key<S: UnificationStoreBase> = <S as UnificationStoreBase>::Key;
 
+#[allow(type_alias_bounds)]
+type Index<S: UnificationStoreBase> = <Key<S> as UnifyKey>::Index;
+
 /// Largely internal trait implemented by the unification table
 /// backing store types. The most common such type is `InPlace`,
 /// which indicates a standard, mutable unification table.
@@ -21,13 +27,40 @@ pub trait UnificationStoreBase: ops::Index<usize, Output = VarValue<Key<Self>>>
 
     fn len(&self) -> usize;
 
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the index of the key at `position`, for `position` in
+    /// `0..len()`, in order of creation. The keys of a dense store are
+    /// exactly `0..len()`, so this is the identity.
+    fn index_at(&self, position: usize) -> usize {
+        position
+    }
+
+    /// The inverse of `index_at`: returns the position of the key with
+    /// the given index, or `None` if the store has no such key.
+    fn position_of(&self, index: usize) -> Option<usize> {
+        if index < self.len() {
+            Some(index)
+        } else {
+            None
+        }
+    }
+
+    /// Returns an index not used by any key, for the next key created
+    /// by `UnificationTable::new_key`.
+    fn next_index(&self) -> usize {
+        self.len()
+    }
+
     fn tag() -> &'static str {
         Self::Key::tag()
     }
 }
 
 pub trait UnificationStoreMut: UnificationStoreBase {
-    fn reset_unifications(&mut self, value: impl FnMut(u32) -> VarValue<Self::Key>);
+    fn reset_unifications(&mut self, value: impl FnMut(Index<Self>) -> VarValue<Self::Key>);
 
     fn push(&mut self, value: VarValue<Self::Key>);
 
@@ -36,6 +69,70 @@ pub trait UnificationStoreMut: UnificationStoreBase {
     fn update<F>(&mut self, index: usize, op: F)
     where
         F: FnOnce(&mut VarValue<Self::Key>);
+
+    /// Called with every key passed to a mutating lookup of the table,
+    /// such as `find` or `unify_var_var`, before it is looked up.
+    /// Stores that create keys lazily insert a missing `key` as a root
+    /// of its own with the given weight and return true. Dense stores,
+    /// whose keys all come from `new_key`, do nothing.
+    fn touch(&mut self, _key: Self::Key, _weight: Index<Self>) -> bool {
+        false
+    }
+
+    /// Checks the invariants of the store itself, such as those of its
+    /// undo log. The invariants of the keys are checked by
+    /// `UnificationTable::check_invariants`.
+    fn check_invariants(&self) -> Result<(), InvariantError> {
+        Ok(())
+    }
+
+    /// Shortens the path from a key to its root; used by `find`.
+    /// Stores with an undo log may record all of the old parents as a
+    /// single action. `CompressPath` is not exported, so this can only
+    /// be called, or overridden, by this crate.
+    #[doc(hidden)]
+    fn compress_path(&mut self, path: CompressPath<Self::Key>) {
+        path.run(self, |store, index, parent| {
+            store.update(index, |value| value.parent = parent)
+        });
+    }
+}
+
+/// A path for `UnificationStoreMut::compress_path` to shorten: from
+/// `key`, which is at least two steps away from its root, to `root`.
+pub struct CompressPath<K> {
+    pub(crate) key: K,
+    pub(crate) root: K,
+    pub(crate) compression: PathCompression,
+}
+
+impl<K: UnifyKey> CompressPath<K> {
+    /// Walks the path, reading the parents from `store` and calling
+    /// `redirect` with the index of each key to point at a new parent.
+    /// Every parent is read before its child is redirected.
+    pub(crate) fn run<S>(self, store: &mut S, mut redirect: impl FnMut(&mut S, usize, K))
+    where
+        S: ops::Index<usize, Output = VarValue<K>> + ?Sized,
+    {
+        let root = self.root;
+        let parent = |store: &S, key: K| store[key.index().as_usize()].parent;
+        let mut current = self.key;
+        loop {
+            let next = parent(store, current);
+            if next == root {
+                break;
+            }
+            let new_parent = match self.compression {
+                PathCompression::Full => root,
+                PathCompression::Splitting | PathCompression::Halving => parent(store, next),
+            };
+            redirect(store, current.index().as_usize(), new_parent);
+            current = match self.compression {
+                PathCompression::Full | PathCompression::Splitting => next,
+                PathCompression::Halving => new_parent,
+            };
+        }
+    }
 }
 
 pub trait UnificationStore: UnificationStoreMut {
@@ -90,8 +187,8 @@ where
     L: UndoLogs<sv::UndoLog<Delegate<K>>>,
 {
     #[inline]
-    fn reset_unifications(&mut self, mut value: impl FnMut(u32) -> VarValue<Self::Key>) {
-        self.values.set_all(|i| value(i as u32));
+    fn reset_unifications(&mut self, mut value: impl FnMut(Index<Self>) -> VarValue<Self::Key>) {
+        self.values.set_all(|i| value(K::Index::from_usize(i)));
     }
 
     #[inline]
@@ -111,6 +208,25 @@ where
     {
         self.values.update(index, op)
     }
+
+    fn check_invariants(&self) -> Result<(), InvariantError> {
+        self.values.check_invariants()
+    }
+
+    #[inline]
+    fn compress_path(&mut self, path: CompressPath<Self::Key>) {
+        let in_snapshot = self.values.in_snapshot();
+        let mut old_parents = Vec::new();
+        path.run(&mut self.values, |values, index, parent| {
+            let old_parent = mem::replace(&mut values.get_mut(index).parent, parent);
+            if in_snapshot {
+                old_parents.push((index, old_parent));
+            }
+        });
+        if in_snapshot {
+            self.values.record(RestoreParents(old_parents));
+        }
+    }
 }
 
 impl<K, V, L> UnificationStore for InPlace<K, V, L>
@@ -153,18 +269,57 @@ where
     }
 }
 
+#[cfg(feature = "serde")]
+impl<K, V, L> serde::Serialize for InPlace<K, V, L>
+where
+    K: UnifyKey,
+    K::Value: serde::Serialize,
+    V: sv::VecLike<Delegate<K>>,
+    L: UndoLogs<sv::UndoLog<Delegate<K>>>,
+{
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.values.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, K, V, L> serde::Deserialize<'de> for InPlace<K, V, L>
+where
+    K: UnifyKey,
+    K::Value: serde::Deserialize<'de>,
+    V: sv::VecLike<Delegate<K>> + Default,
+    L: Default,
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(InPlace {
+            values: sv::SnapshotVec::deserialize(deserializer)?,
+        })
+    }
+}
+
 #[doc(hidden)]
 #[derive(Copy, Clone, Debug)]
 pub struct Delegate<K>(PhantomData<K>);
 
+/// Undo action for a batch of redirects performed by path
+/// compression: the index of each redirected key and its old parent.
+#[derive(Clone, Debug)]
+pub struct RestoreParents<K>(Vec<(usize, K)>);
+
 impl<K: UnifyKey> sv::SnapshotVecDelegate for Delegate<K> {
     type Value = VarValue<K>;
-    type Undo = ();
+    type Undo = RestoreParents<K>;
 
-    fn reverse(_: &mut Vec<VarValue<K>>, _: ()) {}
+    fn reverse(values: &mut Vec<VarValue<K>>, undo: RestoreParents<K>) {
+        for (index, parent) in undo.0.into_iter().rev() {
+            values[index].parent = parent;
+        }
+    }
 }
 
-impl<K: UnifyKey> Rollback<sv::UndoLog<Delegate<K>>> for super::UnificationTableStorage<K> {
+impl<K: UnifyKey, U, O> Rollback<sv::UndoLog<Delegate<K>>>
+    for super::UnificationTableStorage<K, U, O>
+{
     fn reverse(&mut self, undo: sv::UndoLog<Delegate<K>>) {
         self.values.values.reverse(undo);
     }
@@ -199,12 +354,12 @@ impl<K: UnifyKey> UnificationStoreBase for Persistent<K> {
 #[cfg(feature = "persistent")]
 impl<K: UnifyKey> UnificationStoreMut for Persistent<K> {
     #[inline]
-    fn reset_unifications(&mut self, mut value: impl FnMut(u32) -> VarValue<Self::Key>) {
+    fn reset_unifications(&mut self, mut value: impl FnMut(Index<Self>) -> VarValue<Self::Key>) {
         // Without extending dogged, there isn't obviously a more
         // efficient way to do this. But it's pretty dumb. Maybe
         // dogged needs a `map`.
         for i in 0..self.values.len() {
-            self.values[i] = value(i as u32);
+            self.values[i] = value(K::Index::from_usize(i));
         }
     }
 
@@ -261,3 +416,29 @@ where
         &self.values[index]
     }
 }
+
+#[cfg(all(feature = "persistent", feature = "serde"))]
+impl<K> serde::Serialize for Persistent<K>
+where
+    K: UnifyKey,
+    K::Value: serde::Serialize,
+{
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq((0..self.values.len()).map(|i| &self.values[i]))
+    }
+}
+
+#[cfg(all(feature = "persistent", feature = "serde"))]
+impl<'de, K> serde::Deserialize<'de> for Persistent<K>
+where
+    K: UnifyKey,
+    K::Value: serde::Deserialize<'de>,
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut values = DVec::new();
+        for value in Vec::<VarValue<K>>::deserialize(deserializer)? {
+            values.push(value);
+        }
+        Ok(Persistent { values })
+    }
+}
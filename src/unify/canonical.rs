@@ -0,0 +1,90 @@
+//! Dense, deterministic ids for the classes of a unification table.
+//!
+//! `UnificationTable::canonicalize` numbers the classes `0..n` in
+//! order of their smallest key. The numbering depends only on which
+//! keys are unioned, not on the order of the unions or on which key
+//! happens to be the root, so it can be handed to later passes.
+
+use alloc::vec::Vec;
+
+use super::{key_from_usize, UnifyIndex, UnifyKey};
+
+/// The class ids of a unification table at the time it was
+/// canonicalized; see `UnificationTable::canonicalize`. Keys created
+/// afterwards have no class id. Class ids have the index type of the
+/// keys, `u32` unless the key says otherwise.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Canonical<K: UnifyKey> {
+    /// The class id of each key, indexed by key. There are no more
+    /// classes than keys, so the index type of the keys fits the ids.
+    class_of: Vec<K::Index>,
+
+    /// The keys of each class, in increasing order: the keys of class
+    /// `c` are `members[starts[c]..starts[c + 1]]`.
+    starts: Vec<usize>,
+    members: Vec<K>,
+}
+
+impl<K: UnifyKey> Canonical<K> {
+    /// Builds the mapping from the root of every key, indexed by key.
+    pub(super) fn new(roots: &[K]) -> Self {
+        // Visiting keys in increasing order assigns ids in order of
+        // the smallest key of each class.
+        let mut root_class = vec![None; roots.len()];
+        let mut class_of = Vec::with_capacity(roots.len());
+        let mut num_classes = 0;
+        for root in roots {
+            let class = root_class[root.index().as_usize()].get_or_insert_with(|| {
+                num_classes += 1;
+                K::Index::from_usize(num_classes - 1)
+            });
+            class_of.push(*class);
+        }
+
+        // Bucket the keys by class; keys within a class stay sorted.
+        let mut starts = vec![0; num_classes + 1];
+        for &class in &class_of {
+            starts[class.as_usize() + 1] += 1;
+        }
+        for class in 0..num_classes {
+            starts[class + 1] += starts[class];
+        }
+        let mut next = starts.clone();
+        let mut members = vec![None; roots.len()];
+        for (index, &class) in class_of.iter().enumerate() {
+            members[next[class.as_usize()]] = Some(key_from_usize(index));
+            next[class.as_usize()] += 1;
+        }
+
+        Canonical {
+            class_of,
+            starts,
+            members: members.into_iter().map(Option::unwrap).collect(),
+        }
+    }
+
+    /// Returns the number of classes.
+    pub fn num_classes(&self) -> K::Index {
+        K::Index::from_usize(self.starts.len() - 1)
+    }
+
+    /// Returns the id of the class of `key`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` was created after the table was canonicalized.
+    pub fn class_of(&self, key: K) -> K::Index {
+        self.class_of[key.index().as_usize()]
+    }
+
+    /// Returns the keys of the class `class`, in increasing order.
+    pub fn members(&self, class: K::Index) -> &[K] {
+        let class = class.as_usize();
+        &self.members[self.starts[class]..self.starts[class + 1]]
+    }
+
+    /// Returns the smallest key of the class `class`.
+    pub fn representative(&self, class: K::Index) -> K {
+        self.members(class)[0]
+    }
+}
@@ -0,0 +1,108 @@
+//! Export of the union-find forest of a unification table in the DOT
+//! language of Graphviz, for debugging. Every key is a node labelled
+//! with the tag of its key type and its index, and has an edge to its
+//! parent. Roots are drawn with a double border and also show their
+//! weight and value.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Range;
+
+use super::{Snapshot, UnificationStore, UnificationStoreBase, UnificationTable, UnifyIndex};
+use super::{UnifyKey, UnifyValue};
+
+/// The DOT rendering of a unification table, created by
+/// `UnificationTable::to_dot`. The graph is written by the `Display`
+/// impl, so use `to_string()` or `write!` to obtain it.
+pub struct Dot<'a, S: UnificationStoreBase + 'a, U: 'a, O: 'a> {
+    table: &'a UnificationTable<S, U, O>,
+    group_classes: bool,
+    highlight: Range<usize>,
+}
+
+impl<'a, S: UnificationStoreBase, U, O> Dot<'a, S, U, O> {
+    pub(super) fn new(table: &'a UnificationTable<S, U, O>) -> Self {
+        Dot {
+            table,
+            group_classes: false,
+            highlight: 0..0,
+        }
+    }
+
+    /// If `group_classes` is true, the keys of each class are drawn
+    /// inside a box of their own.
+    pub fn group_classes(mut self, group_classes: bool) -> Self {
+        self.group_classes = group_classes;
+        self
+    }
+
+    /// Highlights the keys created since `snapshot` was taken; see
+    /// `UnificationTable::vars_since_snapshot`.
+    pub fn highlight_since(mut self, snapshot: &Snapshot<S>) -> Self
+    where
+        S: UnificationStore,
+    {
+        let keys = self.table.vars_since_snapshot(snapshot);
+        self.highlight = keys.start.index().as_usize()..keys.end.index().as_usize();
+        self
+    }
+
+    fn write_node(&self, f: &mut fmt::Formatter, index: usize) -> fmt::Result {
+        let value = &self.table.values[index];
+        let mut label = format!("{}({})", S::tag(), index);
+        if value.parent.index().as_usize() == index {
+            label += &format!("\nweight {:?}\n{:?}", value.weight, value.value);
+        }
+        write!(f, "n{} [label=\"{}\"", index, escape(&label))?;
+        if value.parent.index().as_usize() == index {
+            write!(f, ", peripheries=2")?;
+        }
+        if self.highlight.contains(&index) {
+            write!(f, ", style=filled, fillcolor=yellow")?;
+        }
+        writeln!(f, "];")
+    }
+}
+
+impl<'a, S, K, V, U, O> fmt::Display for Dot<'a, S, U, O>
+where
+    S: UnificationStoreBase<Key = K, Value = V>,
+    K: UnifyKey<Value = V>,
+    V: UnifyValue,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "digraph \"{}\" {{", escape(S::tag()))?;
+        if self.group_classes {
+            for (root, _, members) in self.table.classes() {
+                writeln!(f, "subgraph cluster_{} {{", root.index().as_usize())?;
+                let mut members: Vec<usize> = members.map(|key| key.index().as_usize()).collect();
+                members.sort();
+                for index in members {
+                    self.write_node(f, index)?;
+                }
+                writeln!(f, "}}")?;
+            }
+        } else {
+            for position in 0..self.table.values.len() {
+                self.write_node(f, self.table.values.index_at(position))?;
+            }
+        }
+        for position in 0..self.table.values.len() {
+            let index = self.table.values.index_at(position);
+            let parent = self.table.values[index].parent.index().as_usize();
+            if parent != index {
+                writeln!(f, "n{} -> n{};", index, parent)?;
+            }
+        }
+        writeln!(f, "}}")
+    }
+}
+
+/// Escapes `s` for use inside a quoted DOT string. Newlines become
+/// line breaks of the label.
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,284 @@
+//! A unification table that remembers *why* keys were unioned.
+//!
+//! Each union is annotated with a user-supplied reason. Alongside the
+//! union-find forest, we maintain a *proof forest* (in the style of
+//! Nieuwenhuis and Oliveras): every successful union of two distinct
+//! classes adds one edge labeled with its reason. Two keys are in the
+//! same class exactly when they are connected in the proof forest, so
+//! `explain` can return the reasons along the path between them.
+//!
+//! Unlike the union-find forest, the proof forest is never
+//! compressed: its edges are exactly the unions that were performed.
+//! To add an edge between two trees, the smaller one is first
+//! re-rooted at the endpoint of the new edge, which takes `O(n log n)`
+//! time over all unions.
+
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use snapshot_vec as sv;
+
+use super::{
+    NoError, Snapshot, UnificationStore, UnificationTable, UnifyIndex, UnifyKey, UnifyValue,
+};
+
+/// A unification table where each union carries a reason of type `R`.
+/// See the module documentation for details.
+#[derive(Clone, Debug)]
+pub struct ExplainingUnificationTable<S: UnificationStore, R> {
+    table: UnificationTable<S>,
+    proofs: sv::SnapshotVec<ProofDelegate<S::Key, R>>,
+}
+
+/// A node in the proof forest. `edge` points towards the root of the
+/// proof tree, along with the reason for the union it represents.
+/// The proof tree of a key holds the keys of its class, so `size`, the
+/// number of keys in the class, is kept up to date only for the key
+/// that is the root of the class in the unification table.
+#[derive(Clone, Debug)]
+struct ProofNode<K, R> {
+    edge: Option<(K, R)>,
+    size: usize,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct ProofDelegate<K, R>(PhantomData<(K, R)>);
+
+impl<K, R> sv::SnapshotVecDelegate for ProofDelegate<K, R> {
+    type Value = ProofNode<K, R>;
+    type Undo = ();
+
+    fn reverse(_: &mut Vec<ProofNode<K, R>>, _: ()) {}
+}
+
+/// A snapshot of an `ExplainingUnificationTable`; rolling back also
+/// forgets the reasons recorded since the snapshot was taken.
+#[must_use = "snapshots must be committed or rolled back"]
+pub struct ExplainingSnapshot<S: UnificationStore> {
+    table: Snapshot<S>,
+    proofs: sv::Snapshot,
+}
+
+impl<S: UnificationStore + Default, R> Default for ExplainingUnificationTable<S, R> {
+    fn default() -> Self {
+        ExplainingUnificationTable {
+            table: UnificationTable::default(),
+            proofs: sv::SnapshotVec::new(),
+        }
+    }
+}
+
+impl<S: UnificationStore + Default, R> ExplainingUnificationTable<S, R> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<S, K, V, R> ExplainingUnificationTable<S, R>
+where
+    S: UnificationStore<Key = K, Value = V>,
+    K: UnifyKey<Value = V>,
+    V: UnifyValue,
+    R: Clone,
+{
+    /// Returns the underlying unification table.
+    pub fn table(&self) -> &UnificationTable<S> {
+        &self.table
+    }
+
+    /// Returns the number of keys created so far.
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    /// Returns true if no keys have been created yet.
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+
+    /// Creates a fresh key with the given value.
+    pub fn new_key(&mut self, value: V) -> K {
+        let key = self.table.new_key(value);
+        self.proofs.push(ProofNode {
+            edge: None,
+            size: 1,
+        });
+        key
+    }
+
+    /// Given a key, returns the (current) root key.
+    pub fn find<K1>(&mut self, id: K1) -> K
+    where
+        K1: Into<K>,
+    {
+        self.table.find(id)
+    }
+
+    /// Given two keys, indicates whether they have been unioned together.
+    pub fn unioned<K1, K2>(&mut self, a_id: K1, b_id: K2) -> bool
+    where
+        K1: Into<K>,
+        K2: Into<K>,
+    {
+        self.table.unioned(a_id, b_id)
+    }
+
+    /// Returns the current value for the given key.
+    pub fn probe_value<K1>(&mut self, id: K1) -> V
+    where
+        K1: Into<K>,
+    {
+        self.table.probe_value(id)
+    }
+
+    /// Sets the value of the key `a_id` to `b`, attempting to merge
+    /// with the previous value. Values play no part in explanations.
+    pub fn unify_var_value<K1>(&mut self, a_id: K1, b: V) -> Result<(), V::Error>
+    where
+        K1: Into<K>,
+    {
+        self.table.unify_var_value(a_id, b)
+    }
+
+    /// Unions two keys without the possibility of failure; only
+    /// applicable when unify values use `NoError` as their error
+    /// type.
+    pub fn union<K1, K2>(&mut self, a_id: K1, b_id: K2, reason: R)
+    where
+        K1: Into<K>,
+        K2: Into<K>,
+        V: UnifyValue<Error = NoError>,
+    {
+        self.unify_var_var(a_id, b_id, reason).unwrap();
+    }
+
+    /// Unions together two variables, merging their values, and
+    /// records `reason` as the justification. If the keys are already
+    /// unioned, `reason` is discarded. If merging the values fails,
+    /// the error is propagated and this method has no effect.
+    pub fn unify_var_var<K1, K2>(&mut self, a_id: K1, b_id: K2, reason: R) -> Result<(), V::Error>
+    where
+        K1: Into<K>,
+        K2: Into<K>,
+    {
+        let a_id = a_id.into();
+        let b_id = b_id.into();
+        let a_root = self.table.find(a_id);
+        let b_root = self.table.find(b_id);
+        if a_root == b_root {
+            return Ok(());
+        }
+
+        self.table.unify_var_var(a_root, b_root)?;
+        let a_size = self.proofs[a_root.index().as_usize()].size;
+        let b_size = self.proofs[b_root.index().as_usize()].size;
+        let root = self.table.find(a_root);
+        self.proofs
+            .update(root.index().as_usize(), |node| node.size = a_size + b_size);
+
+        // Re-root the proof tree of the smaller class so that the new
+        // edge can hang it off the other one.
+        let (child, parent) = if a_size <= b_size {
+            (a_id, b_id)
+        } else {
+            (b_id, a_id)
+        };
+        self.reroot(child);
+        self.proofs.update(child.index().as_usize(), |node| {
+            node.edge = Some((parent, reason))
+        });
+        Ok(())
+    }
+
+    /// Returns the reasons for the unions that connect `a_id` to
+    /// `b_id`, in order from `a_id` to `b_id`, or `None` if the two
+    /// keys are not unioned. These are the labels of the unique path
+    /// between the two keys in the proof forest, so no reason appears
+    /// twice and none is irrelevant to the conclusion.
+    pub fn explain<K1, K2>(&mut self, a_id: K1, b_id: K2) -> Option<Vec<R>>
+    where
+        K1: Into<K>,
+        K2: Into<K>,
+    {
+        let a_id = a_id.into();
+        let b_id = b_id.into();
+        if !self.table.unioned(a_id, b_id) {
+            return None;
+        }
+
+        // Both paths end at the root of the proof tree; below their
+        // common part, they meet at the nearest common ancestor.
+        let mut path_a = self.path_to_root(a_id);
+        let mut path_b = self.path_to_root(b_id);
+        while path_a.len() > 1
+            && path_b.len() > 1
+            && path_a[path_a.len() - 2] == path_b[path_b.len() - 2]
+        {
+            path_a.pop();
+            path_b.pop();
+        }
+        path_a.pop();
+        path_b.pop();
+
+        let reason = |key: &K| self.edge(*key).as_ref().unwrap().1.clone();
+        let mut reasons: Vec<R> = path_a.iter().map(reason).collect();
+        reasons.extend(path_b.iter().rev().map(reason));
+        Some(reasons)
+    }
+
+    /// Starts a new snapshot. Each snapshot must be either
+    /// rolled back or committed in a "LIFO" (stack) order.
+    pub fn snapshot(&mut self) -> ExplainingSnapshot<S> {
+        ExplainingSnapshot {
+            table: self.table.snapshot(),
+            proofs: self.proofs.start_snapshot(),
+        }
+    }
+
+    /// Reverses all changes since the last snapshot, including the
+    /// reasons recorded since then.
+    pub fn rollback_to(&mut self, snapshot: ExplainingSnapshot<S>) {
+        self.table.rollback_to(snapshot.table);
+        self.proofs.rollback_to(snapshot.proofs);
+    }
+
+    /// Commits all changes since the last snapshot. Of course, they
+    /// can still be undone if there is a snapshot further out.
+    pub fn commit(&mut self, snapshot: ExplainingSnapshot<S>) {
+        self.table.commit(snapshot.table);
+        self.proofs.commit(snapshot.proofs);
+    }
+
+    fn edge(&self, key: K) -> &Option<(K, R)> {
+        &self.proofs[key.index().as_usize()].edge
+    }
+
+    /// Returns the keys on the path from `key` to the root of its proof
+    /// tree, both included.
+    fn path_to_root(&self, mut key: K) -> Vec<K> {
+        let mut path = vec![key];
+        while let Some((parent, _)) = *self.edge(key) {
+            key = parent;
+            path.push(key);
+        }
+        path
+    }
+
+    /// Makes `key` the root of its proof tree by reversing the edges
+    /// on the path from `key` to the current root.
+    fn reroot(&mut self, key: K) {
+        let mut previous = None;
+        let mut current = key;
+        loop {
+            let edge = self.edge(current).clone();
+            self.proofs
+                .update(current.index().as_usize(), |node| node.edge = previous);
+            match edge {
+                Some((parent, reason)) => {
+                    previous = Some((current, reason));
+                    current = parent;
+                }
+                None => break,
+            }
+        }
+    }
+}
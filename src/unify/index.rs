@@ -0,0 +1,66 @@
+//! The integer types that can be used as the index of a unification
+//! key. A table of keys with a narrow index type uses less memory, and
+//! one with a wide index type can hold more keys.
+
+use core::fmt::Debug;
+use core::hash::Hash;
+
+/// An unsigned integer type used as the index of a unification key;
+/// see `UnifyKey::Index`. Implemented for `u16`, `u32`, `u64` and
+/// `usize`. Tables do not create a key with the largest value of the
+/// type as its index, so a table with `u16` indices holds up to 65535
+/// keys.
+pub trait UnifyIndex: Copy + Debug + Eq + Ord + Hash {
+    /// Converts `index` to this type, returning `None` if it does not
+    /// fit. The largest value of the type fits.
+    fn try_from_usize(index: usize) -> Option<Self>;
+
+    /// Converts this index to a `usize`. Indices are only ever created
+    /// from a `usize`, so this does not lose information.
+    fn as_usize(self) -> usize;
+
+    /// Adds two indices, saturating at the largest value of the type.
+    fn saturating_add(self, other: Self) -> Self;
+
+    /// Converts `index` to this type.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` does not fit, e.g. when creating the 65536th
+    /// key of a table whose keys use `u16` indices.
+    fn from_usize(index: usize) -> Self {
+        match Self::try_from_usize(index) {
+            Some(index) => index,
+            None => panic!("index {} does not fit in the index type of the key", index),
+        }
+    }
+}
+
+macro_rules! unify_index {
+    ($($t:ident),*) => {
+        $(
+            impl UnifyIndex for $t {
+                #[inline]
+                fn try_from_usize(index: usize) -> Option<Self> {
+                    if index as u64 <= $t::MAX as u64 {
+                        Some(index as $t)
+                    } else {
+                        None
+                    }
+                }
+
+                #[inline]
+                fn as_usize(self) -> usize {
+                    self as usize
+                }
+
+                #[inline]
+                fn saturating_add(self, other: Self) -> Self {
+                    $t::saturating_add(self, other)
+                }
+            }
+        )*
+    };
+}
+
+unify_index!(u16, u32, u64, usize);
@@ -0,0 +1,150 @@
+//! Values that are bounded from below and above in a lattice.
+//!
+//! `UnifyValue` merges the values of two classes symmetrically, which
+//! suits equality constraints. Subtyping-style inference instead
+//! gathers lower and upper bounds on each variable: a lower bound `l`
+//! means the variable is at least `l`, an upper bound `u` that it is
+//! at most `u`. `Bounds` implements `UnifyValue` by joining lower
+//! bounds and meeting upper bounds, and fails as soon as the lower
+//! bound of a class is no longer below its upper bound. Since it is an
+//! ordinary value, it lives in an ordinary `UnificationTable` and is
+//! rolled back with its snapshots.
+
+use core::fmt::Debug;
+
+use super::{
+    UnificationObserver, UnificationStoreMut, UnificationTable, UnifyKey, UnifyValue, UnionStrategy,
+};
+
+/// A lattice: a partial order `le` in which any two elements have a
+/// least upper bound (`join`) and a greatest lower bound (`meet`).
+pub trait LatticeValue: Clone + Debug {
+    /// Returns the least element that is above both `self` and `other`.
+    fn join(&self, other: &Self) -> Self;
+
+    /// Returns the greatest element that is below both `self` and `other`.
+    fn meet(&self, other: &Self) -> Self;
+
+    /// Returns true if `self` is below or equal to `other`.
+    fn le(&self, other: &Self) -> bool;
+}
+
+/// The bounds of a class. A missing bound means the class is not
+/// bounded on that side.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bounds<L> {
+    lower: Option<L>,
+    upper: Option<L>,
+}
+
+/// Error returned when the lower bound of a class would no longer be
+/// below its upper bound.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BoundsError<L> {
+    /// The lower bound that would have resulted.
+    pub lower: L,
+    /// The upper bound that would have resulted.
+    pub upper: L,
+}
+
+impl<L> Default for Bounds<L> {
+    fn default() -> Self {
+        Bounds::unbounded()
+    }
+}
+
+impl<L> Bounds<L> {
+    /// Bounds that admit any element.
+    pub fn unbounded() -> Self {
+        Bounds {
+            lower: None,
+            upper: None,
+        }
+    }
+
+    /// Bounds that admit the elements above `lower`.
+    pub fn at_least(lower: L) -> Self {
+        Bounds {
+            lower: Some(lower),
+            upper: None,
+        }
+    }
+
+    /// Bounds that admit the elements below `upper`.
+    pub fn at_most(upper: L) -> Self {
+        Bounds {
+            lower: None,
+            upper: Some(upper),
+        }
+    }
+
+    /// Returns the lower bound, if any.
+    pub fn lower(&self) -> Option<&L> {
+        self.lower.as_ref()
+    }
+
+    /// Returns the upper bound, if any.
+    pub fn upper(&self) -> Option<&L> {
+        self.upper.as_ref()
+    }
+}
+
+fn combine<L>(a: &Option<L>, b: &Option<L>, f: impl FnOnce(&L, &L) -> L) -> Option<L>
+where
+    L: Clone,
+{
+    match (a, b) {
+        (Some(a), Some(b)) => Some(f(a, b)),
+        (Some(x), None) | (None, Some(x)) => Some(x.clone()),
+        (None, None) => None,
+    }
+}
+
+impl<L: LatticeValue> UnifyValue for Bounds<L> {
+    type Error = BoundsError<L>;
+
+    fn unify_values(a: &Self, b: &Self) -> Result<Self, BoundsError<L>> {
+        let lower = combine(&a.lower, &b.lower, L::join);
+        let upper = combine(&a.upper, &b.upper, L::meet);
+        if let (Some(lower), Some(upper)) = (&lower, &upper) {
+            if !lower.le(upper) {
+                return Err(BoundsError {
+                    lower: lower.clone(),
+                    upper: upper.clone(),
+                });
+            }
+        }
+        Ok(Bounds { lower, upper })
+    }
+}
+
+impl<S, K, L, U, O> UnificationTable<S, U, O>
+where
+    S: UnificationStoreMut<Key = K, Value = Bounds<L>>,
+    K: UnifyKey<Value = Bounds<L>>,
+    L: LatticeValue,
+    U: UnionStrategy,
+    O: UnificationObserver<K>,
+{
+    /// Requires the class of `id` to be at least `lower`, joining it
+    /// with its current lower bound. If the new lower bound is not
+    /// below the upper bound, the error is returned and this method
+    /// has no effect.
+    pub fn add_lower_bound<K1>(&mut self, id: K1, lower: L) -> Result<(), BoundsError<L>>
+    where
+        K1: Into<K>,
+    {
+        self.unify_var_value(id, Bounds::at_least(lower))
+    }
+
+    /// Requires the class of `id` to be at most `upper`, meeting it
+    /// with its current upper bound. If the new upper bound is not
+    /// above the lower bound, the error is returned and this method
+    /// has no effect.
+    pub fn add_upper_bound<K1>(&mut self, id: K1, upper: L) -> Result<(), BoundsError<L>>
+    where
+        K1: Into<K>,
+    {
+        self.unify_var_value(id, Bounds::at_most(upper))
+    }
+}
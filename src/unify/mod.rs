@@ -31,14 +31,19 @@
 //! The best way to see how it is used is to read the `tests.rs` file;
 //! search for e.g. `UnitKey`.
 
-use std::fmt::Debug;
-use std::marker;
-use std::ops::Range;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use core::marker;
+use core::ops::{self, Range};
 
+use invariants::InvariantError;
 use snapshot_vec::{self as sv, UndoLog};
-use undo_log::{UndoLogs, VecLog};
+#[cfg(feature = "stats")]
+use undo_log::UndoLogStats;
+use undo_log::{DropBomb, RollbackGuard, UndoLogs, VecLog};
 
 mod backing_vec;
+use self::backing_vec::CompressPath;
 pub use self::backing_vec::{
     Delegate, InPlace, UnificationStore, UnificationStoreBase, UnificationStoreMut,
 };
@@ -46,6 +51,33 @@ pub use self::backing_vec::{
 #[cfg(feature = "persistent")]
 pub use self::backing_vec::Persistent;
 
+mod canonical;
+pub use self::canonical::Canonical;
+
+mod dot;
+pub use self::dot::Dot;
+
+mod explain;
+pub use self::explain::{ExplainingSnapshot, ExplainingUnificationTable};
+
+mod index;
+pub use self::index::UnifyIndex;
+
+mod lattice;
+pub use self::lattice::{Bounds, BoundsError, LatticeValue};
+
+mod observer;
+pub use self::observer::UnificationObserver;
+
+mod potential;
+pub use self::potential::{Group, PotentialError, PotentialSnapshot, PotentialUnificationTable};
+
+mod sparse;
+pub use self::sparse::{Sparse, SparseSnapshot};
+
+mod strategy;
+pub use self::strategy::{ByIndex, ByRank, BySize, UnionStrategy};
+
 #[cfg(test)]
 mod tests;
 
@@ -63,9 +95,14 @@ mod tests;
 pub trait UnifyKey: Copy + Clone + Debug + PartialEq {
     type Value: UnifyValue;
 
-    fn index(&self) -> u32;
+    /// The integer type of the key's index, which bounds the number of
+    /// keys in a table. This is usually `u32`; use `u16` to save memory
+    /// in small tables, or `u64` or `usize` for very large ones.
+    type Index: UnifyIndex;
 
-    fn from_index(u: u32) -> Self;
+    fn index(&self) -> Self::Index;
+
+    fn from_index(u: Self::Index) -> Self;
 
     fn tag() -> &'static str;
 
@@ -76,7 +113,8 @@ pub trait UnifyKey: Copy + Clone + Debug + PartialEq {
     /// control what value is returned from `find()`. In general, it
     /// is better to let the unification table determine the root,
     /// since overriding the rank can cause execution time to increase
-    /// dramatically.
+    /// dramatically. If you only want a different balanced policy,
+    /// pick another `UnionStrategy` for the table instead.
     #[allow(unused_variables)]
     fn order_roots(
         a: Self,
@@ -146,19 +184,80 @@ pub struct NoError {
     _dummy: (),
 }
 
+/// Error returned by `unify_var_var_detailed`: the keys that were
+/// unified, their roots and the values of those roots, along with the
+/// error returned by `UnifyValue::unify_values`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnifyError<K, V: UnifyValue> {
+    pub a_key: K,
+    pub a_root: K,
+    pub a_value: V,
+    pub b_key: K,
+    pub b_root: K,
+    pub b_value: V,
+    pub error: V::Error,
+}
+
+/// Error returned by `unify_var_value_detailed`: the key, its root
+/// and the value of the root, the value it was unified with, and the
+/// error returned by `UnifyValue::unify_values`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnifyValueError<K, V: UnifyValue> {
+    pub key: K,
+    pub root: K,
+    pub root_value: V,
+    pub value: V,
+    pub error: V::Error,
+}
+
+/// Returns the key with the given index.
+///
+/// # Panics
+///
+/// Panics if `index` does not fit in the index type of `K`.
+fn key_from_usize<K: UnifyKey>(index: usize) -> K {
+    K::from_index(K::Index::from_usize(index))
+}
+
+/// Checks that `index` may be used for a new key of type `K`.
+///
+/// # Panics
+///
+/// Panics if `index` is the largest index of `K`. It is never used, so
+/// that the end of a range of keys such as `vars_since_snapshot` can
+/// be represented.
+fn assert_index_not_reserved<K: UnifyKey>(index: usize) {
+    let reserved = index
+        .checked_add(1)
+        .and_then(K::Index::try_from_usize)
+        .is_none();
+    assert!(
+        !reserved,
+        "{}: the largest index {} is reserved and cannot be used for a key",
+        K::tag(),
+        index
+    );
+}
+
 /// Value of a unification key. We implement Tarjan's union-find
 /// algorithm: when two keys are unified, one of them is converted
 /// into a "redirect" pointing at the other. These redirects form a
 /// DAG: the roots of the DAG (nodes that are not redirected) are each
-/// associated with a value of type `V` and a rank. The rank is used
-/// to keep the DAG relatively balanced, which helps keep the running
-/// time of the algorithm under control. For more information, see
+/// associated with a value of type `V` and a weight. The weight is
+/// used by the table's `UnionStrategy` to keep the DAG relatively
+/// balanced, which helps keep the running time of the algorithm under
+/// control. For more information, see
 /// <http://en.wikipedia.org/wiki/Disjoint-set_data_structure>.
+///
+/// In addition, the members of each class are linked into a circular
+/// list through `next`, so that the class can be enumerated without
+/// scanning the whole table.
 #[derive(PartialEq, Clone, Debug)]
 pub struct VarValue<K: UnifyKey> {
-    parent: K,       // if equal to self, this is a root
-    value: K::Value, // value assigned (only relevant to root)
-    rank: u32,       // max depth (only relevant to root)
+    parent: K,        // if equal to self, this is a root
+    value: K::Value,  // value assigned (only relevant to root)
+    weight: K::Index, // rank or size (only relevant to root)
+    next: K,          // next member of the same class
 }
 
 /// Table of unification keys and their values. You must define a key type K
@@ -174,14 +273,121 @@ pub struct VarValue<K: UnifyKey> {
 ///     cloning the table is an O(1) operation.
 ///   - This implies that ordinary operations are quite a bit slower though.
 ///   - Requires the `persistent` feature be selected in your Cargo.toml file.
-#[derive(Clone, Debug, Default)]
-pub struct UnificationTable<S: UnificationStoreBase> {
+///
+/// The second type parameter selects the `UnionStrategy` that picks
+/// the new root when two classes are unioned; it defaults to union by
+/// rank. The third is a `UnificationObserver` that is notified of
+/// unions, value changes and snapshots; it defaults to `()`, which
+/// ignores them.
+#[derive(Clone, Debug)]
+pub struct UnificationTable<S: UnificationStoreBase, U = ByRank, O = ()> {
     /// Indicates the current value of each key.
     values: S,
+
+    /// How `find` shortens the paths it walks.
+    compression: PathCompression,
+
+    /// The number of roots, if known. It is unknown for tables that
+    /// share their storage with an external undo log (see `with_log`),
+    /// since rolling back that log bypasses the table.
+    num_classes: Option<usize>,
+
+    strategy: marker::PhantomData<U>,
+
+    /// Notified of unions and value changes.
+    observer: O,
+
+    #[cfg(feature = "stats")]
+    stats: UnifyStats,
+}
+
+/// Counters collected by a table when the `stats` feature is enabled;
+/// see `UnificationTable::stats`. Rolling back a snapshot does not
+/// reset them.
+#[cfg(feature = "stats")]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UnifyStats {
+    /// The number of times the root of a key was looked up, not
+    /// counting the `_shared` methods, which take `&self`.
+    pub finds: u64,
+    /// The total number of parent pointers followed by those lookups.
+    pub total_path_length: u64,
+    /// The largest number of parent pointers followed by one lookup.
+    pub max_path_length: usize,
+    /// The number of lookups that shortened the path they walked.
+    pub compressions: u64,
+    /// The number of times two distinct classes were unioned.
+    pub unions: u64,
+}
+
+#[cfg(feature = "stats")]
+impl UnifyStats {
+    /// Returns the average number of parent pointers followed by a
+    /// lookup, or zero if there were none.
+    pub fn average_path_length(&self) -> f64 {
+        if self.finds == 0 {
+            0.0
+        } else {
+            self.total_path_length as f64 / self.finds as f64
+        }
+    }
+}
+
+// A manual impl avoids a `Default` bound on `U`.
+impl<S: UnificationStoreBase + Default, U, O: Default> Default for UnificationTable<S, U, O> {
+    fn default() -> Self {
+        UnificationTable::with_observer(O::default())
+    }
+}
+
+/// The flavor of path compression performed by `find`. All three give
+/// the same asymptotic bounds; they differ in how many keys each find
+/// updates, which matters when updates are costly (for example, when
+/// they have to be logged inside a snapshot).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum PathCompression {
+    /// Point every key on the path directly at the root. This is the
+    /// default.
+    #[default]
+    Full,
+
+    /// Point every other key on the path at its grandparent.
+    Halving,
+
+    /// Point every key on the path at its grandparent.
+    Splitting,
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for PathCompression {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let tag: u8 = match *self {
+            PathCompression::Full => 0,
+            PathCompression::Halving => 1,
+            PathCompression::Splitting => 2,
+        };
+        tag.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for PathCompression {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match u8::deserialize(deserializer)? {
+            0 => Ok(PathCompression::Full),
+            1 => Ok(PathCompression::Halving),
+            2 => Ok(PathCompression::Splitting),
+            tag => Err(serde::de::Error::invalid_value(
+                serde::de::Unexpected::Unsigned(u64::from(tag)),
+                &"a path compression tag between 0 and 2",
+            )),
+        }
+    }
 }
 
 pub type UnificationStorage<K> = Vec<VarValue<K>>;
-pub type UnificationTableStorage<K> = UnificationTable<InPlace<K, UnificationStorage<K>, ()>>;
+pub type UnificationTableStorage<K, U = ByRank, O = ()> =
+    UnificationTable<InPlace<K, UnificationStorage<K>, ()>, U, O>;
 
 /// A unification table that uses an "in-place" vector.
 #[allow(type_alias_bounds)]
@@ -189,31 +395,93 @@ pub type InPlaceUnificationTable<
     K: UnifyKey,
     V: sv::VecLike<Delegate<K>> = Vec<VarValue<K>>,
     L = VecLog<UndoLog<Delegate<K>>>,
-> = UnificationTable<InPlace<K, V, L>>;
+    U = ByRank,
+    O = (),
+> = UnificationTable<InPlace<K, V, L>, U, O>;
+
+/// A unification table for sparse keys, which are created on first use.
+#[allow(type_alias_bounds)]
+pub type SparseUnificationTable<K: UnifyKey, U = ByRank, O = ()> =
+    UnificationTable<Sparse<K>, U, O>;
 
 /// A unification table that uses a "persistent" vector.
 #[cfg(feature = "persistent")]
 #[allow(type_alias_bounds)]
-pub type PersistentUnificationTable<K: UnifyKey> = UnificationTable<Persistent<K>>;
+pub type PersistentUnificationTable<K: UnifyKey, U = ByRank, O = ()> =
+    UnificationTable<Persistent<K>, U, O>;
 
 /// At any time, users may snapshot a unification table.  The changes
 /// made during the snapshot may either be *committed* or *rolled back*.
+/// In debug builds with the `std` feature, dropping a snapshot without
+/// doing either panics; see `UnificationTable::snapshot_guard` for a
+/// snapshot that rolls back when dropped.
+#[must_use = "snapshots must be committed or rolled back"]
 pub struct Snapshot<S: UnificationStore> {
     // Link snapshot to the unification store `S` of the table.
     marker: marker::PhantomData<S>,
     snapshot: S::Snapshot,
+    num_classes: Option<usize>,
+    bomb: DropBomb,
+}
+
+/// A snapshot of a unification table that borrows the table and rolls
+/// back all changes made through it when dropped, unless `commit` is
+/// called first. The table is accessed through the guard. Created by
+/// `UnificationTable::snapshot_guard`.
+#[must_use = "dropping the guard immediately rolls the snapshot back"]
+pub struct SnapshotGuard<'a, S: UnificationStore, U = ByRank, O = ()> {
+    guard: RollbackGuard<&'a mut UnificationTable<S, U, O>, Snapshot<S>>,
+}
+
+impl<'a, S, U, O> SnapshotGuard<'a, S, U, O>
+where
+    S: UnificationStore,
+    O: UnificationObserver<S::Key>,
+{
+    /// Keeps the changes made since the snapshot was taken. Of course,
+    /// they can still be undone if there is a snapshot further out.
+    pub fn commit(self) {
+        self.guard.commit(|ut, snapshot| ut.commit(snapshot));
+    }
+
+    /// Reverses all changes made since the snapshot was taken; this is
+    /// the same as dropping the guard.
+    pub fn rollback(self) {
+        self.guard.rollback();
+    }
+
+    /// Returns the keys of all variables created since the snapshot
+    /// was taken.
+    pub fn vars_since_snapshot(&self) -> Range<S::Key> {
+        self.guard.get().vars_since_snapshot(self.guard.snapshot())
+    }
+}
+
+impl<'a, S: UnificationStore, U, O> ops::Deref for SnapshotGuard<'a, S, U, O> {
+    type Target = UnificationTable<S, U, O>;
+
+    fn deref(&self) -> &UnificationTable<S, U, O> {
+        self.guard.get()
+    }
+}
+
+impl<'a, S: UnificationStore, U, O> ops::DerefMut for SnapshotGuard<'a, S, U, O> {
+    fn deref_mut(&mut self) -> &mut UnificationTable<S, U, O> {
+        self.guard.target()
+    }
 }
 
 impl<K: UnifyKey> VarValue<K> {
-    fn new_var(key: K, value: K::Value) -> VarValue<K> {
-        VarValue::new(key, value, 0)
+    fn new_var(key: K, value: K::Value, weight: K::Index) -> VarValue<K> {
+        VarValue::new(key, value, weight)
     }
 
-    fn new(parent: K, value: K::Value, rank: u32) -> VarValue<K> {
+    fn new(parent: K, value: K::Value, weight: K::Index) -> VarValue<K> {
         VarValue {
-            parent: parent, // this is a root
-            value: value,
-            rank: rank,
+            parent, // this is a root
+            value,
+            weight,
+            next: parent, // a class of its own
         }
     }
 
@@ -221,29 +489,251 @@ impl<K: UnifyKey> VarValue<K> {
         self.parent = to;
     }
 
-    fn root(&mut self, rank: u32, value: K::Value) {
-        self.rank = rank;
+    fn root(&mut self, weight: K::Index, value: K::Value) {
+        self.weight = weight;
         self.value = value;
     }
 }
 
-impl<K> UnificationTableStorage<K>
+/// Keys are serialized through their index, so `K` itself need not
+/// implement serde's traits. Indices and weights are written as `u64`
+/// whatever the index type of the key.
+#[cfg(feature = "serde")]
+impl<K: UnifyKey> serde::Serialize for VarValue<K>
+where
+    K::Value: serde::Serialize,
+{
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (
+            self.parent.index().as_usize() as u64,
+            &self.value,
+            self.weight.as_usize() as u64,
+            self.next.index().as_usize() as u64,
+        )
+            .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, K: UnifyKey> serde::Deserialize<'de> for VarValue<K>
+where
+    K::Value: serde::Deserialize<'de>,
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        fn index<I: UnifyIndex, E: serde::de::Error>(index: u64) -> Result<I, E> {
+            core::convert::TryFrom::try_from(index)
+                .ok()
+                .and_then(I::try_from_usize)
+                .ok_or_else(|| E::custom(format!("index {} is too large", index)))
+        }
+
+        let (parent, value, weight, next) = <(u64, K::Value, u64, u64)>::deserialize(deserializer)?;
+        Ok(VarValue {
+            parent: K::from_index(index(parent)?),
+            value,
+            weight: index(weight)?,
+            next: K::from_index(index(next)?),
+        })
+    }
+}
+
+/// A table is serialized as its values followed by its path
+/// compression; the observer is not serialized. Deserializing checks
+/// the parents, weights and member lists of the keys as
+/// `check_invariants` does, and recounts the classes.
+#[cfg(feature = "serde")]
+impl<S, U, O> serde::Serialize for UnificationTable<S, U, O>
+where
+    S: UnificationStoreBase + serde::Serialize,
+{
+    fn serialize<Ser: serde::Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        (&self.values, self.compression).serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, S, U, O> serde::Deserialize<'de> for UnificationTable<S, U, O>
+where
+    S: UnificationStoreBase + serde::Deserialize<'de>,
+    O: Default,
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (values, compression) = <(S, PathCompression)>::deserialize(deserializer)?;
+        let mut table = UnificationTable {
+            values,
+            compression,
+            num_classes: None,
+            strategy: marker::PhantomData,
+            observer: O::default(),
+            #[cfg(feature = "stats")]
+            stats: UnifyStats::default(),
+        };
+        let num_classes = table.check_classes().map_err(serde::de::Error::custom)?;
+        table.num_classes = Some(num_classes);
+        Ok(table)
+    }
+}
+
+impl<S: UnificationStoreMut, U, O> UnificationTable<S, U, O> {
+    /// Checks that every parent and next member is a key of the table,
+    /// that the parents form a forest, that weights strictly increase
+    /// toward the roots, that the member lists match the classes, that
+    /// the count of classes is right, and that the undo log, if any, is
+    /// consistent. Returns the first violation found. This takes
+    /// linear time and does not modify the table.
+    pub fn check_invariants(&self) -> Result<(), InvariantError> {
+        let num_roots = self.check_classes()?;
+        if let Some(counted) = self.num_classes {
+            if counted != num_roots {
+                return Err(InvariantError::WrongClassCount {
+                    counted,
+                    roots: num_roots,
+                });
+            }
+        }
+
+        self.values.check_invariants()
+    }
+}
+
+impl<S: UnificationStoreBase, U, O> UnificationTable<S, U, O> {
+    /// Checks the parents, weights and member lists of the keys for
+    /// `check_invariants`, and returns the number of classes.
+    fn check_classes(&self) -> Result<usize, InvariantError> {
+        // The checks below work on the positions of the keys in the
+        // store, which are `0..len` even if their indices are not.
+        let len = self.values.len();
+        let mut parents = Vec::with_capacity(len);
+        let mut nexts = Vec::with_capacity(len);
+        for position in 0..len {
+            let key = self.values.index_at(position);
+            let value = &self.values[key];
+            let parent = value.parent.index().as_usize();
+            match self.values.position_of(parent) {
+                Some(parent) => parents.push(parent),
+                None => return Err(InvariantError::ParentOutOfRange { key, parent }),
+            }
+            let next = value.next.index().as_usize();
+            match self.values.position_of(next) {
+                Some(next) => nexts.push(next),
+                None => return Err(InvariantError::NextOutOfRange { key, next }),
+            }
+        }
+        let index_at = |position| self.values.index_at(position);
+
+        // Find the root of every key, remembering the roots found so
+        // far so that every parent pointer is followed only once.
+        let mut roots: Vec<Option<usize>> = vec![None; len];
+        let mut on_path = vec![false; len];
+        let mut path = Vec::new();
+        for key in 0..len {
+            let mut current = key;
+            let root = loop {
+                if let Some(root) = roots[current] {
+                    break root;
+                }
+                let parent = parents[current];
+                if parent == current {
+                    break current;
+                }
+                if on_path[current] {
+                    return Err(InvariantError::Cycle { key: index_at(key) });
+                }
+                on_path[current] = true;
+                path.push(current);
+                current = parent;
+            };
+            roots[current] = Some(root);
+            for visited in path.drain(..) {
+                on_path[visited] = false;
+                roots[visited] = Some(root);
+            }
+        }
+
+        let mut class_sizes = vec![0; len];
+        for key in 0..len {
+            let parent = parents[key];
+            if parent != key
+                && self.values[index_at(key)].weight >= self.values[index_at(parent)].weight
+            {
+                return Err(InvariantError::WeightNotIncreasing {
+                    key: index_at(key),
+                    parent: index_at(parent),
+                });
+            }
+            class_sizes[roots[key].unwrap()] += 1;
+        }
+
+        let mut num_roots = 0;
+        let mut seen = vec![false; len];
+        for root in (0..len).filter(|&key| roots[key] == Some(key)) {
+            num_roots += 1;
+            let mut current = root;
+            let mut size = 0;
+            loop {
+                if roots[current] != Some(root) || seen[current] {
+                    return Err(InvariantError::BrokenMemberList {
+                        root: index_at(root),
+                    });
+                }
+                seen[current] = true;
+                size += 1;
+                current = nexts[current];
+                if current == root {
+                    break;
+                }
+            }
+            if size != class_sizes[root] {
+                return Err(InvariantError::BrokenMemberList {
+                    root: index_at(root),
+                });
+            }
+        }
+
+        Ok(num_roots)
+    }
+}
+
+#[cfg(feature = "stats")]
+impl<K, V, U, O> UnificationTable<InPlace<K, V, VecLog<UndoLog<Delegate<K>>>>, U, O>
+where
+    K: UnifyKey,
+    V: sv::VecLike<Delegate<K>>,
+{
+    /// Returns the counters collected by the undo log of the table.
+    pub fn undo_log_stats(&self) -> UndoLogStats {
+        self.values.values.undo_log_stats()
+    }
+}
+
+impl<K, U, O> UnificationTableStorage<K, U, O>
 where
     K: UnifyKey,
 {
     /// Creates a `UnificationTable` using an external `undo_log`, allowing mutating methods to be
-    /// called if `L` does not implement `UndoLogs`
-    pub fn with_log<'a, L>(
-        &'a mut self,
+    /// called if `L` does not implement `UndoLogs`.
+    ///
+    /// Since changes made through the returned table can be rolled back behind this table's back,
+    /// `num_classes` falls back to counting the roots afterwards. The returned table notifies this
+    /// table's observer, but collects statistics of its own.
+    pub fn with_log<L>(
+        &mut self,
         undo_log: L,
-    ) -> UnificationTable<InPlace<K, &'a mut UnificationStorage<K>, L>>
+    ) -> UnificationTable<InPlace<K, &mut UnificationStorage<K>, L>, U, &mut O>
     where
         L: UndoLogs<sv::UndoLog<Delegate<K>>>,
     {
+        self.num_classes = None;
         UnificationTable {
             values: InPlace {
                 values: self.values.values.with_log(undo_log),
             },
+            compression: self.compression,
+            num_classes: None,
+            strategy: marker::PhantomData,
+            observer: &mut self.observer,
+            #[cfg(feature = "stats")]
+            stats: UnifyStats::default(),
         }
     }
 }
@@ -253,19 +743,51 @@ where
 // other type parameter U, and we have no way to say
 // Option<U>:LatticeValue.
 
-impl<S: UnificationStoreBase + Default> UnificationTable<S> {
+impl<S: UnificationStoreBase + Default, U: UnionStrategy, O: Default> UnificationTable<S, U, O> {
     pub fn new() -> Self {
         Self::default()
     }
 }
 
-impl<S: UnificationStore> UnificationTable<S> {
+impl<S: UnificationStoreBase + Default, U, O> UnificationTable<S, U, O> {
+    /// Creates an empty table that notifies `observer` of its changes.
+    pub fn with_observer(observer: O) -> Self {
+        UnificationTable {
+            values: S::default(),
+            compression: PathCompression::default(),
+            num_classes: Some(0),
+            strategy: marker::PhantomData,
+            observer,
+            #[cfg(feature = "stats")]
+            stats: UnifyStats::default(),
+        }
+    }
+}
+
+impl<S, U, O> UnificationTable<S, U, O>
+where
+    S: UnificationStore,
+    O: UnificationObserver<S::Key>,
+{
     /// Starts a new snapshot. Each snapshot must be either
     /// rolled back or committed in a "LIFO" (stack) order.
     pub fn snapshot(&mut self) -> Snapshot<S> {
+        self.observer.on_snapshot();
         Snapshot {
             marker: marker::PhantomData::<S>,
             snapshot: self.values.start_snapshot(),
+            num_classes: self.num_classes,
+            bomb: DropBomb::new(),
+        }
+    }
+
+    /// Starts a new snapshot that is rolled back when the returned
+    /// guard is dropped, unless `SnapshotGuard::commit` is called. The
+    /// table can be used through the guard in the meantime.
+    pub fn snapshot_guard(&mut self) -> SnapshotGuard<'_, S, U, O> {
+        let snapshot = self.snapshot();
+        SnapshotGuard {
+            guard: RollbackGuard::new(self, snapshot, |ut, snapshot| ut.rollback_to(snapshot)),
         }
     }
 
@@ -274,6 +796,9 @@ impl<S: UnificationStore> UnificationTable<S> {
     pub fn rollback_to(&mut self, snapshot: Snapshot<S>) {
         debug!("{}: rollback_to()", S::tag());
         self.values.rollback_to(snapshot.snapshot);
+        self.num_classes = snapshot.num_classes;
+        snapshot.bomb.defuse();
+        self.observer.on_rollback_to();
     }
 
     /// Commits all changes since the last snapshot. Of course, they
@@ -281,34 +806,99 @@ impl<S: UnificationStore> UnificationTable<S> {
     pub fn commit(&mut self, snapshot: Snapshot<S>) {
         debug!("{}: commit()", S::tag());
         self.values.commit(snapshot.snapshot);
+        snapshot.bomb.defuse();
+        self.observer.on_commit();
+    }
+
+    /// Runs `f` inside a new snapshot and then rolls back the changes
+    /// it made, returning the result of `f`. The changes are rolled
+    /// back even if `f` panics.
+    pub fn probe<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+        let snapshot = self.snapshot();
+        let mut guard = RollbackGuard::new(self, snapshot, |ut, snapshot| ut.rollback_to(snapshot));
+        f(guard.target())
     }
 
+    /// Runs `f` inside a new snapshot, which is committed if `f`
+    /// returns `Ok` and rolled back otherwise. The changes are rolled
+    /// back, too, if `f` panics.
+    pub fn commit_if_ok<R, E>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<R, E>,
+    ) -> Result<R, E> {
+        let snapshot = self.snapshot();
+        let mut guard = RollbackGuard::new(self, snapshot, |ut, snapshot| ut.rollback_to(snapshot));
+        let result = f(guard.target());
+        if result.is_ok() {
+            guard.commit(|ut, snapshot| ut.commit(snapshot));
+        }
+        result
+    }
+}
+
+impl<S: UnificationStore, U, O> UnificationTable<S, U, O> {
     /// Returns the keys of all variables created since the `snapshot`.
     pub fn vars_since_snapshot(&self, snapshot: &Snapshot<S>) -> Range<S::Key> {
         let range = self.values.values_since_snapshot(&snapshot.snapshot);
-        S::Key::from_index(range.start as u32)..S::Key::from_index(range.end as u32)
+        key_from_usize(range.start)..key_from_usize(range.end)
     }
 }
 
-impl<S: UnificationStoreBase> UnificationTable<S> {
+impl<S: UnificationStoreBase, U, O> UnificationTable<S, U, O> {
+    /// Returns the observer of this table.
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+
+    /// Returns the observer of this table, e.g. to drain the events it
+    /// has collected.
+    pub fn observer_mut(&mut self) -> &mut O {
+        &mut self.observer
+    }
+
+    /// Returns the flavor of path compression used by `find`.
+    pub fn path_compression(&self) -> PathCompression {
+        self.compression
+    }
+
+    /// Selects the flavor of path compression used by `find`.
+    pub fn set_path_compression(&mut self, compression: PathCompression) {
+        self.compression = compression;
+    }
+
     /// Returns the number of keys created so far.
     pub fn len(&self) -> usize {
         self.values.len()
     }
 
+    /// Returns true if no keys have been created yet.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     /// Obtains the current value for a particular key.
     /// Not for end-users; they can use `probe_value`.
     fn value(&self, key: S::Key) -> &VarValue<S::Key> {
-        &self.values[key.index() as usize]
+        &self.values[key.index().as_usize()]
     }
 }
 
-impl<S: UnificationStoreMut> UnificationTable<S> {
+impl<S, U, O> UnificationTable<S, U, O>
+where
+    S: UnificationStoreMut,
+    U: UnionStrategy,
+    O: UnificationObserver<S::Key>,
+{
     /// Creates a fresh key with the given value.
     pub fn new_key(&mut self, value: S::Value) -> S::Key {
-        let len = self.values.len();
-        let key: S::Key = UnifyKey::from_index(len as u32);
-        self.values.push(VarValue::new_var(key, value));
+        let index = self.values.next_index();
+        assert_index_not_reserved::<S::Key>(index);
+        let key: S::Key = key_from_usize(index);
+        self.values
+            .push(VarValue::new_var(key, value, U::initial_weight()));
+        if let Some(ref mut num_classes) = self.num_classes {
+            *num_classes += 1;
+        }
         debug!("{}: created new key: {:?}", S::tag(), key);
         key
     }
@@ -324,10 +914,11 @@ impl<S: UnificationStoreMut> UnificationTable<S> {
     /// the closure.
     pub fn reset_unifications(&mut self, mut value: impl FnMut(S::Key) -> S::Value) {
         self.values.reset_unifications(|i| {
-            let key = UnifyKey::from_index(i as u32);
+            let key = UnifyKey::from_index(i);
             let value = value(key);
-            VarValue::new_var(key, value)
+            VarValue::new_var(key, value, U::initial_weight())
         });
+        self.num_classes = Some(self.values.len());
     }
 
     /// Find the root node for `vid`. This uses the standard
@@ -339,21 +930,41 @@ impl<S: UnificationStoreMut> UnificationTable<S> {
     ///
     /// This is an always-inlined version of this function for the hot
     /// callsites. `uninlined_get_root_key` is the never-inlined version.
+    /// It only handles the cases where there is nothing to compress
+    /// itself, leaving longer paths to `compress_path`.
     #[inline(always)]
     fn inlined_get_root_key(&mut self, vid: S::Key) -> S::Key {
-        let v = self.value(vid);
-        if v.parent == vid {
+        if self.values.touch(vid, U::initial_weight()) {
+            if let Some(ref mut num_classes) = self.num_classes {
+                *num_classes += 1;
+            }
+            debug!("{}: created new key: {:?}", S::tag(), vid);
+        }
+
+        let redirect = self.value(vid).parent;
+        if redirect == vid {
+            self.record_find(0);
             return vid;
         }
 
-        let redirect = v.parent;
-        let root_key: S::Key = self.uninlined_get_root_key(redirect);
-        if root_key != redirect {
-            // Path compression
-            self.update_value(vid, |value| value.parent = root_key);
+        if self.value(redirect).parent == redirect {
+            self.record_find(1);
+            return redirect;
         }
 
-        root_key
+        self.compress_path(vid)
+    }
+
+    /// Counts a lookup that followed `path_length` parent pointers.
+    #[inline(always)]
+    #[cfg_attr(not(feature = "stats"), allow(unused_variables))]
+    fn record_find(&mut self, path_length: usize) {
+        #[cfg(feature = "stats")]
+        {
+            self.stats.finds += 1;
+            self.stats.total_path_length += path_length as u64;
+            self.stats.max_path_length = self.stats.max_path_length.max(path_length);
+        }
     }
 
     // This is a never-inlined version of this function for cold callsites.
@@ -363,17 +974,57 @@ impl<S: UnificationStoreMut> UnificationTable<S> {
         self.inlined_get_root_key(vid)
     }
 
+    /// Walks from `vid` (which is at least two steps away from its
+    /// root) up to the root, then walks the path again to shorten it
+    /// according to the table's `PathCompression`. This is iterative,
+    /// so that long paths cannot overflow the stack, and lets the store
+    /// log all of the redirects as a single action.
+    #[inline(never)]
+    fn compress_path(&mut self, vid: S::Key) -> S::Key {
+        let mut root = vid;
+        let mut path_length = 0;
+        loop {
+            let parent = self.value(root).parent;
+            if parent == root {
+                break;
+            }
+            root = parent;
+            path_length += 1;
+        }
+
+        self.values.compress_path(CompressPath {
+            key: vid,
+            root,
+            compression: self.compression,
+        });
+        debug!("{}: compressed path from {:?} to {:?}", S::tag(), vid, root);
+        self.record_find(path_length);
+        #[cfg(feature = "stats")]
+        {
+            self.stats.compressions += 1;
+        }
+
+        root
+    }
+
+    /// Replaces the value of `root`, notifying the observer.
+    fn set_root_value(&mut self, root: S::Key, value: S::Value) {
+        self.observer
+            .on_value_change(root, &self.values[root.index().as_usize()].value, &value);
+        self.update_value(root, |node| node.value = value);
+    }
+
     fn update_value<OP>(&mut self, key: S::Key, op: OP)
     where
         OP: FnOnce(&mut VarValue<S::Key>),
     {
-        self.values.update(key.index() as usize, op);
+        self.values.update(key.index().as_usize(), op);
         debug!("Updated variable {:?} to {:?}", key, self.value(key));
     }
 
     /// Either redirects `node_a` to `node_b` or vice versa, depending
-    /// on the relative rank. The value associated with the new root
-    /// will be `new_value`.
+    /// on `UnifyKey::order_roots` and the table's `UnionStrategy`. The
+    /// value associated with the new root will be `new_value`.
     ///
     /// NB: This is the "union" operation of "union-find". It is
     /// really more of a building block. If the values associated with
@@ -382,71 +1033,81 @@ impl<S: UnificationStoreMut> UnificationTable<S> {
     fn unify_roots(&mut self, key_a: S::Key, key_b: S::Key, new_value: S::Value) {
         debug!("unify(key_a={:?}, key_b={:?})", key_a, key_b);
 
-        let rank_a = self.value(key_a).rank;
-        let rank_b = self.value(key_b).rank;
-        if let Some((new_root, redirected)) = S::Key::order_roots(
+        let weight_a = self.value(key_a).weight;
+        let weight_b = self.value(key_b).weight;
+        let (new_root, redirected) = match S::Key::order_roots(
             key_a,
             &self.value(key_a).value,
             key_b,
             &self.value(key_b).value,
         ) {
-            // compute the new rank for the new root that they chose;
-            // this may not be the optimal choice.
-            let new_rank = if new_root == key_a {
-                debug_assert!(redirected == key_b);
-                if rank_a > rank_b {
-                    rank_a
-                } else {
-                    rank_b + 1
-                }
-            } else {
-                debug_assert!(new_root == key_b);
-                debug_assert!(redirected == key_a);
-                if rank_b > rank_a {
-                    rank_b
-                } else {
-                    rank_a + 1
-                }
-            };
-            self.redirect_root(new_rank, redirected, new_root, new_value);
-        } else if rank_a > rank_b {
-            // a has greater rank, so a should become b's parent,
-            // i.e., b should redirect to a.
-            self.redirect_root(rank_a, key_b, key_a, new_value);
-        } else if rank_a < rank_b {
-            // b has greater rank, so a should redirect to b.
-            self.redirect_root(rank_b, key_a, key_b, new_value);
+            Some((new_root, redirected)) => {
+                debug_assert!(
+                    (new_root == key_a && redirected == key_b)
+                        || (new_root == key_b && redirected == key_a)
+                );
+                (new_root, redirected)
+            }
+            None => U::order_roots(key_a, weight_a, key_b, weight_b),
+        };
+
+        // compute the new weight for the new root; if `order_roots`
+        // overrode the strategy, this may not be the optimal choice.
+        let new_weight = if new_root == key_a {
+            U::merged_weight(weight_a, weight_b)
         } else {
-            // If equal, redirect one to the other and increment the
-            // other's rank.
-            self.redirect_root(rank_a + 1, key_a, key_b, new_value);
-        }
+            U::merged_weight(weight_b, weight_a)
+        };
+        self.redirect_root(new_weight, redirected, new_root, new_value);
     }
 
     /// Internal method to redirect `old_root_key` (which is currently
     /// a root) to a child of `new_root_key` (which will remain a
-    /// root). The rank and value of `new_root_key` will be updated to
-    /// `new_rank` and `new_value` respectively.
+    /// root). The weight and value of `new_root_key` will be updated to
+    /// `new_weight` and `new_value` respectively.
+    ///
+    /// This also splices the member lists of the two classes together
+    /// by swapping the `next` pointers of the two roots.
     fn redirect_root(
         &mut self,
-        new_rank: u32,
+        new_weight: <S::Key as UnifyKey>::Index,
         old_root_key: S::Key,
         new_root_key: S::Key,
         new_value: S::Value,
     ) {
+        let old_root_next = self.value(old_root_key).next;
+        let new_root_next = self.value(new_root_key).next;
+        self.observer.on_union(
+            new_root_key,
+            old_root_key,
+            (
+                &self.values[new_root_key.index().as_usize()].value,
+                &self.values[old_root_key.index().as_usize()].value,
+            ),
+            &new_value,
+        );
+        if let Some(ref mut num_classes) = self.num_classes {
+            *num_classes -= 1;
+        }
+        #[cfg(feature = "stats")]
+        {
+            self.stats.unions += 1;
+        }
         self.update_value(old_root_key, |old_root_value| {
             old_root_value.redirect(new_root_key);
+            old_root_value.next = new_root_next;
         });
         self.update_value(new_root_key, |new_root_value| {
-            new_root_value.root(new_rank, new_value);
+            new_root_value.root(new_weight, new_value);
+            new_root_value.next = old_root_next;
         });
     }
 }
 
-/// ////////////////////////////////////////////////////////////////////////
-/// Public API
+///////////////////////////////////////////////////////////////////////////
+// Public API
 
-impl<S, K, V> UnificationTable<S>
+impl<S, K, V, U, O> UnificationTable<S, U, O>
 where
     S: UnificationStoreBase<Key = K, Value = V>,
     K: UnifyKey<Value = V>,
@@ -466,13 +1127,169 @@ where
         }
         None
     }
+
+    /// Given a key, returns the (current) root key. Unlike `find`,
+    /// this does not perform path compression, so it only needs a
+    /// shared reference; prefer `find` when you have a `&mut`.
+    pub fn find_shared<K1>(&self, id: K1) -> K
+    where
+        K1: Into<K>,
+    {
+        let mut id = id.into();
+        loop {
+            let parent = self.value(id).parent;
+            if parent == id {
+                return id;
+            }
+            id = parent;
+        }
+    }
+
+    /// Given two keys, indicates whether they have been unioned
+    /// together, without performing path compression.
+    pub fn unioned_shared<K1, K2>(&self, a_id: K1, b_id: K2) -> bool
+    where
+        K1: Into<K>,
+        K2: Into<K>,
+    {
+        self.find_shared(a_id) == self.find_shared(b_id)
+    }
+
+    /// Returns the current value for the given key, without
+    /// performing path compression.
+    pub fn probe_value_shared<K1>(&self, id: K1) -> V
+    where
+        K1: Into<K>,
+    {
+        let id = self.find_shared(id);
+        self.value(id).value.clone()
+    }
+
+    /// Returns the number of classes, i.e. the number of roots. This
+    /// takes constant time, except for tables that share their storage
+    /// with an external undo log, where it counts the roots.
+    pub fn num_classes(&self) -> usize {
+        match self.num_classes {
+            Some(num_classes) => num_classes,
+            None => self.roots().count(),
+        }
+    }
+
+    /// Returns an iterator over the roots of all classes, in order of
+    /// their creation (which is the order of their index, unless the
+    /// store is `Sparse`).
+    pub fn roots(&self) -> Roots<'_, S> {
+        Roots {
+            values: &self.values,
+            next: 0,
+        }
+    }
+
+    /// Returns an iterator over all classes, in the order of their
+    /// roots; see `roots`. Each class is given as its root, its value and an
+    /// iterator over its members.
+    pub fn classes(&self) -> Classes<'_, S> {
+        Classes {
+            roots: self.roots(),
+        }
+    }
+
+    /// Returns the counters collected so far; see `UnifyStats`.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> UnifyStats {
+        self.stats.clone()
+    }
+
+    /// Returns a DOT (Graphviz) rendering of the parent pointers,
+    /// weights and root values of the table, for debugging. See `Dot`
+    /// for the available options.
+    pub fn to_dot(&self) -> Dot<'_, S, U, O> {
+        Dot::new(self)
+    }
+
+    /// Returns an iterator over all keys in the same class as `id`,
+    /// starting with `id` itself. Runs in time proportional to the
+    /// size of the class.
+    pub fn members<K1>(&self, id: K1) -> Members<'_, S>
+    where
+        K1: Into<K>,
+    {
+        let id = id.into();
+        Members {
+            values: &self.values,
+            start: id,
+            next: Some(id),
+        }
+    }
+}
+
+/// Iterator over the roots of a table; see `UnificationTable::roots`.
+pub struct Roots<'a, S: UnificationStoreBase + 'a> {
+    values: &'a S,
+    next: usize,
+}
+
+impl<'a, S: UnificationStoreBase> Iterator for Roots<'a, S> {
+    type Item = S::Key;
+
+    fn next(&mut self) -> Option<S::Key> {
+        while self.next < self.values.len() {
+            let key: S::Key = key_from_usize(self.values.index_at(self.next));
+            self.next += 1;
+            if self.values[key.index().as_usize()].parent == key {
+                return Some(key);
+            }
+        }
+        None
+    }
+}
+
+/// Iterator over the classes of a table; see `UnificationTable::classes`.
+pub struct Classes<'a, S: UnificationStoreBase + 'a> {
+    roots: Roots<'a, S>,
+}
+
+impl<'a, S: UnificationStoreBase> Iterator for Classes<'a, S> {
+    type Item = (S::Key, &'a S::Value, Members<'a, S>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let values = self.roots.values;
+        self.roots.next().map(|root| {
+            let members = Members {
+                values,
+                start: root,
+                next: Some(root),
+            };
+            (root, &values[root.index().as_usize()].value, members)
+        })
+    }
 }
 
-impl<S, K, V> UnificationTable<S>
+/// Iterator over the members of a class; see `UnificationTable::members`.
+pub struct Members<'a, S: UnificationStoreBase + 'a> {
+    values: &'a S,
+    start: S::Key,
+    next: Option<S::Key>,
+}
+
+impl<'a, S: UnificationStoreBase> Iterator for Members<'a, S> {
+    type Item = S::Key;
+
+    fn next(&mut self) -> Option<S::Key> {
+        let key = self.next?;
+        let next = self.values[key.index().as_usize()].next;
+        self.next = if next == self.start { None } else { Some(next) };
+        Some(key)
+    }
+}
+
+impl<S, K, V, U, O> UnificationTable<S, U, O>
 where
     S: UnificationStoreMut<Key = K, Value = V>,
     K: UnifyKey<Value = V>,
     V: UnifyValue,
+    U: UnionStrategy,
+    O: UnificationObserver<K>,
 {
     /// Unions two keys without the possibility of failure; only
     /// applicable when unify values use `NoError` as their error
@@ -515,6 +1332,30 @@ where
         self.uninlined_get_root_key(id)
     }
 
+    /// Numbers the classes `0..num_classes` in order of their smallest
+    /// key, and returns the mapping between keys and class ids in both
+    /// directions. This finds the root of every key once, fully
+    /// compressing every path regardless of `path_compression`, and so
+    /// runs in near-linear time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the keys are not `0..len()`, which can only happen
+    /// with a `Sparse` store.
+    pub fn canonicalize(&mut self) -> Canonical<K> {
+        assert!(
+            (0..self.len()).all(|position| self.values.index_at(position) < self.len()),
+            "`canonicalize` requires the keys of the table to be `0..len()`"
+        );
+        let compression = self.compression;
+        self.compression = PathCompression::Full;
+        let roots: Vec<K> = (0..self.len())
+            .map(|index| self.uninlined_get_root_key(key_from_usize(index)))
+            .collect();
+        self.compression = compression;
+        Canonical::new(&roots)
+    }
+
     /// Unions together two variables, merging their values. If
     /// merging the values fails, the error is propagated and this
     /// method has no effect.
@@ -535,7 +1376,8 @@ where
 
         let combined = V::unify_values(&self.value(root_a).value, &self.value(root_b).value)?;
 
-        Ok(self.unify_roots(root_a, root_b, combined))
+        self.unify_roots(root_a, root_b, combined);
+        Ok(())
     }
 
     /// Sets the value of the key `a_id` to `b`, attempting to merge
@@ -547,7 +1389,137 @@ where
         let a_id = a_id.into();
         let root_a = self.uninlined_get_root_key(a_id);
         let value = V::unify_values(&self.value(root_a).value, &b)?;
-        self.update_value(root_a, |node| node.value = value);
+        self.set_root_value(root_a, value);
+        Ok(())
+    }
+
+    /// Like `unify_var_var`, but on failure returns both keys, their
+    /// roots and the values of the roots along with the error, so that
+    /// callers need not probe the table again to report it.
+    pub fn unify_var_var_detailed<K1, K2>(
+        &mut self,
+        a_id: K1,
+        b_id: K2,
+    ) -> Result<(), UnifyError<K, V>>
+    where
+        K1: Into<K>,
+        K2: Into<K>,
+    {
+        let a_id = a_id.into();
+        let b_id = b_id.into();
+        // A failed union has no effect, so the roots can be looked up
+        // again afterwards.
+        self.unify_var_var(a_id, b_id).map_err(|error| {
+            let a_root = self.uninlined_get_root_key(a_id);
+            let b_root = self.uninlined_get_root_key(b_id);
+            UnifyError {
+                a_key: a_id,
+                a_root,
+                a_value: self.value(a_root).value.clone(),
+                b_key: b_id,
+                b_root,
+                b_value: self.value(b_root).value.clone(),
+                error,
+            }
+        })
+    }
+
+    /// Like `unify_var_value`, but on failure returns the key, its
+    /// root and the value of the root along with the rejected value and
+    /// the error.
+    pub fn unify_var_value_detailed<K1>(
+        &mut self,
+        a_id: K1,
+        b: V,
+    ) -> Result<(), UnifyValueError<K, V>>
+    where
+        K1: Into<K>,
+    {
+        let a_id = a_id.into();
+        let root_a = self.uninlined_get_root_key(a_id);
+        match V::unify_values(&self.value(root_a).value, &b) {
+            Ok(value) => {
+                self.set_root_value(root_a, value);
+                Ok(())
+            }
+            Err(error) => Err(UnifyValueError {
+                key: a_id,
+                root: root_a,
+                root_value: self.value(root_a).value.clone(),
+                value: b,
+                error,
+            }),
+        }
+    }
+
+    /// Unions each pair of keys in turn, merging their values. If
+    /// merging the values fails for any pair, the first error is
+    /// propagated and this method has no effect: the pairs before the
+    /// failing one are undone, too. Within a snapshot, the whole batch
+    /// is rolled back with it.
+    pub fn unify_all<I, K1, K2>(&mut self, pairs: I) -> Result<(), V::Error>
+    where
+        S: UnificationStore,
+        I: IntoIterator<Item = (K1, K2)>,
+        K1: Into<K>,
+        K2: Into<K>,
+    {
+        self.commit_if_ok(|this| {
+            pairs
+                .into_iter()
+                .try_for_each(|(a_id, b_id)| this.unify_var_var(a_id, b_id))
+        })
+    }
+
+    /// Sets the value of each key in turn, attempting to merge with
+    /// its previous value. Like `unify_all`, this either succeeds for
+    /// all keys or has no effect.
+    pub fn unify_all_values<I, K1>(&mut self, values: I) -> Result<(), V::Error>
+    where
+        S: UnificationStore,
+        I: IntoIterator<Item = (K1, V)>,
+        K1: Into<K>,
+    {
+        self.commit_if_ok(|this| {
+            values
+                .into_iter()
+                .try_for_each(|(a_id, b)| this.unify_var_value(a_id, b))
+        })
+    }
+
+    /// Replays the unifications of `other` into this table: every key
+    /// of `other` is unified with its root, and every root's value is
+    /// unified into its class. Keys of `other` are mapped to keys of
+    /// this table through `remap`. If merging the values fails, the
+    /// error is propagated and this method has no effect. Within a
+    /// snapshot, the whole import is rolled back with it.
+    pub fn absorb<S2, U2, O2>(
+        &mut self,
+        other: &UnificationTable<S2, U2, O2>,
+        remap: impl Fn(S2::Key) -> K,
+    ) -> Result<(), V::Error>
+    where
+        S: UnificationStore,
+        S2: UnificationStoreBase<Value = V>,
+    {
+        self.commit_if_ok(|this| this.absorb_classes(other, remap))
+    }
+
+    fn absorb_classes<S2, U2, O2>(
+        &mut self,
+        other: &UnificationTable<S2, U2, O2>,
+        remap: impl Fn(S2::Key) -> K,
+    ) -> Result<(), V::Error>
+    where
+        S2: UnificationStoreBase<Value = V>,
+    {
+        for (root, value, members) in other.classes() {
+            let root = remap(root);
+            self.unify_var_value(root, value.clone())?;
+            for member in members {
+                self.unify_var_var(root, remap(member))?;
+            }
+        }
         Ok(())
     }
 
@@ -589,7 +1561,7 @@ impl<V: UnifyValue> UnifyValue for Option<V> {
         match (a, b) {
             (&None, &None) => Ok(None),
             (&Some(ref v), &None) | (&None, &Some(ref v)) => Ok(Some(v.clone())),
-            (&Some(ref a), &Some(ref b)) => match V::unify_values(a, b) {
+            (Some(a), Some(b)) => match V::unify_values(a, b) {
                 Ok(v) => Ok(Some(v)),
                 Err(err) => Err(err),
             },
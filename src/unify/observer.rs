@@ -0,0 +1,81 @@
+//! Hooks that are notified when a unification table changes.
+//!
+//! An observer is stored in the table and called whenever two classes
+//! are unioned or the value of a root is unified with a new value, so
+//! that constraints can be propagated without wrapping every call
+//! site. The observer is not rolled back with the table, and rolling
+//! back does not fire events that undo the earlier ones. Instead, the
+//! observer is told when snapshots are started, rolled back and
+//! committed, including the snapshots that the table takes itself,
+//! e.g. in `unify_all` or `absorb`, so that observers that keep state
+//! can forget the events of a snapshot that is rolled back. Rolling
+//! back an external undo log is not reported.
+
+use super::UnifyKey;
+
+/// Receives the changes made to a `UnificationTable`. All methods do
+/// nothing by default; `()` is the observer that ignores all events.
+pub trait UnificationObserver<K: UnifyKey> {
+    /// Called when the class of `old_root` is merged into the class of
+    /// `new_root`. `old_values` holds the values of `new_root` and
+    /// `old_root` (in that order) before the union, and `new_value`
+    /// the value of the merged class.
+    fn on_union(
+        &mut self,
+        new_root: K,
+        old_root: K,
+        old_values: (&K::Value, &K::Value),
+        new_value: &K::Value,
+    ) {
+        let _ = (new_root, old_root, old_values, new_value);
+    }
+
+    /// Called when the value of `root` is unified with a new value,
+    /// changing it from `old_value` to `new_value`. The two values may
+    /// be equal if the new value added no information.
+    fn on_value_change(&mut self, root: K, old_value: &K::Value, new_value: &K::Value) {
+        let _ = (root, old_value, new_value);
+    }
+
+    /// Called when a snapshot of the table is started.
+    fn on_snapshot(&mut self) {}
+
+    /// Called when the innermost snapshot has been rolled back. The
+    /// events since the matching `on_snapshot` no longer apply.
+    fn on_rollback_to(&mut self) {}
+
+    /// Called when the innermost snapshot has been committed. Its
+    /// events still apply, but are rolled back along with an outer
+    /// snapshot.
+    fn on_commit(&mut self) {}
+}
+
+impl<K: UnifyKey> UnificationObserver<K> for () {}
+
+impl<K: UnifyKey, O: UnificationObserver<K>> UnificationObserver<K> for &mut O {
+    fn on_union(
+        &mut self,
+        new_root: K,
+        old_root: K,
+        old_values: (&K::Value, &K::Value),
+        new_value: &K::Value,
+    ) {
+        O::on_union(self, new_root, old_root, old_values, new_value)
+    }
+
+    fn on_value_change(&mut self, root: K, old_value: &K::Value, new_value: &K::Value) {
+        O::on_value_change(self, root, old_value, new_value)
+    }
+
+    fn on_snapshot(&mut self) {
+        O::on_snapshot(self)
+    }
+
+    fn on_rollback_to(&mut self) {
+        O::on_rollback_to(self)
+    }
+
+    fn on_commit(&mut self) {
+        O::on_commit(self)
+    }
+}
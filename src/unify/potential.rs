@@ -0,0 +1,328 @@
+//! A unification table whose edges carry *offsets*, sometimes called a
+//! weighted or potential union-find.
+//!
+//! Each key `k` implicitly has an unknown position `x_k`, and unifying
+//! `a` with `b` at offset `g` records the fact `x_a = g(x_b)`. Offsets
+//! are elements of a user-supplied `Group`: integers under addition
+//! let you record `a = b + 3`, booleans under exclusive or capture
+//! parity (and hence bipartiteness), and invertible affine maps let
+//! you record `a = 2 * b + 1`.
+//!
+//! Every key stores the offset to its parent. Offsets are composed
+//! along the path when finding the root, and path compression stores
+//! the composed offset so that later finds do not need to walk the
+//! path again.
+
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use core::marker::PhantomData;
+use snapshot_vec as sv;
+
+use super::{assert_index_not_reserved, key_from_usize, NoError, UnifyIndex, UnifyKey, UnifyValue};
+
+/// The offsets of a `PotentialUnificationTable`. Implementations must
+/// satisfy the group laws: `compose` is associative, `identity` is a
+/// neutral element for it, and `x.compose(&x.inverse())` is the
+/// identity. `compose` need not be commutative.
+pub trait Group: Clone + Debug + PartialEq {
+    /// The neutral element, i.e. a zero offset.
+    fn identity() -> Self;
+
+    /// Returns the offset that applies `other` and then `self`.
+    fn compose(&self, other: &Self) -> Self;
+
+    /// Returns the offset that undoes `self`.
+    fn inverse(&self) -> Self;
+}
+
+macro_rules! additive_group {
+    ($($t:ty),*) => {
+        $(
+            /// Integers under (wrapping) addition.
+            impl Group for $t {
+                fn identity() -> Self {
+                    0
+                }
+
+                fn compose(&self, other: &Self) -> Self {
+                    self.wrapping_add(*other)
+                }
+
+                fn inverse(&self) -> Self {
+                    self.wrapping_neg()
+                }
+            }
+        )*
+    };
+}
+
+additive_group!(i8, i16, i32, i64, i128, isize);
+
+/// Booleans under exclusive or, i.e. the integers modulo 2. Use this
+/// to track whether two keys have the same or opposite parity.
+impl Group for bool {
+    fn identity() -> Self {
+        false
+    }
+
+    fn compose(&self, other: &Self) -> Self {
+        *self != *other
+    }
+
+    fn inverse(&self) -> Self {
+        *self
+    }
+}
+
+/// Error returned by `PotentialUnificationTable::unify_var_var`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PotentialError<G, E> {
+    /// The two keys are already related, but by a different offset.
+    Inconsistent {
+        /// The offset that follows from the existing facts.
+        existing: G,
+        /// The offset that was requested.
+        requested: G,
+    },
+
+    /// The values of the two classes could not be merged.
+    Value(E),
+}
+
+/// Value of a key in a `PotentialUnificationTable`: like `VarValue`,
+/// plus the offset from this key to its parent.
+#[derive(Clone, Debug)]
+struct PotentialValue<K: UnifyKey, G> {
+    parent: K,       // if equal to self, this is a root
+    offset: G,       // x_self = offset(x_parent)
+    value: K::Value, // value assigned (only relevant to root)
+    rank: u32,       // max depth (only relevant to root)
+}
+
+#[derive(Copy, Clone, Debug)]
+struct PotentialDelegate<K, G>(PhantomData<(K, G)>);
+
+impl<K: UnifyKey, G> sv::SnapshotVecDelegate for PotentialDelegate<K, G> {
+    type Value = PotentialValue<K, G>;
+    type Undo = ();
+
+    fn reverse(_: &mut Vec<PotentialValue<K, G>>, _: ()) {}
+}
+
+/// A unification table where each union carries an offset of type
+/// `G`. See the module documentation for details.
+#[derive(Clone, Debug)]
+pub struct PotentialUnificationTable<K: UnifyKey, G> {
+    values: sv::SnapshotVec<PotentialDelegate<K, G>>,
+}
+
+/// At any time, users may snapshot a potential unification table. The
+/// changes made during the snapshot may either be *committed* or
+/// *rolled back*.
+#[must_use = "snapshots must be committed or rolled back"]
+pub struct PotentialSnapshot {
+    snapshot: sv::Snapshot,
+}
+
+impl<K: UnifyKey, G> Default for PotentialUnificationTable<K, G> {
+    fn default() -> Self {
+        PotentialUnificationTable {
+            values: sv::SnapshotVec::new(),
+        }
+    }
+}
+
+impl<K: UnifyKey, G: Group> PotentialUnificationTable<K, G> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of keys created so far.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Returns true if no keys have been created yet.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Creates a fresh key with the given value.
+    pub fn new_key(&mut self, value: K::Value) -> K {
+        let index = self.values.len();
+        assert_index_not_reserved::<K>(index);
+        let key: K = key_from_usize(index);
+        self.values.push(PotentialValue {
+            parent: key,
+            offset: G::identity(),
+            value,
+            rank: 0,
+        });
+        debug!("{}: created new key: {:?}", K::tag(), key);
+        key
+    }
+
+    /// Given a key, returns the (current) root key.
+    pub fn find(&mut self, key: K) -> K {
+        self.find_with_offset(key).0
+    }
+
+    /// Given two keys, indicates whether they have been unioned together.
+    pub fn unioned(&mut self, a: K, b: K) -> bool {
+        self.find(a) == self.find(b)
+    }
+
+    /// If `a` and `b` have been unioned, returns the offset `g` such
+    /// that `x_a = g(x_b)`.
+    pub fn offset(&mut self, a: K, b: K) -> Option<G> {
+        let (root_a, offset_a) = self.find_with_offset(a);
+        let (root_b, offset_b) = self.find_with_offset(b);
+        if root_a == root_b {
+            Some(offset_a.compose(&offset_b.inverse()))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the current value for the given key.
+    pub fn probe_value(&mut self, key: K) -> K::Value {
+        let root = self.find(key);
+        self.values[root.index().as_usize()].value.clone()
+    }
+
+    /// Sets the value of the key `a`, attempting to merge with the
+    /// previous value.
+    pub fn unify_var_value(
+        &mut self,
+        a: K,
+        b: K::Value,
+    ) -> Result<(), <K::Value as UnifyValue>::Error> {
+        let root = self.find(a);
+        let value = UnifyValue::unify_values(&self.values[root.index().as_usize()].value, &b)?;
+        self.values
+            .update(root.index().as_usize(), |node| node.value = value);
+        Ok(())
+    }
+
+    /// Records `x_a = offset(x_b)` without the possibility of failure
+    /// in merging values; only applicable when unify values use
+    /// `NoError` as their error type. Fails if `a` and `b` are already
+    /// related by a different offset.
+    pub fn union(&mut self, a: K, b: K, offset: G) -> Result<(), PotentialError<G, NoError>>
+    where
+        K::Value: UnifyValue<Error = NoError>,
+    {
+        self.unify_var_var(a, b, offset)
+    }
+
+    /// Records `x_a = offset(x_b)`, unioning the two keys and merging
+    /// their values. If the keys are already unioned, checks that the
+    /// offset agrees with the existing facts instead. On error, this
+    /// method has no effect.
+    pub fn unify_var_var(
+        &mut self,
+        a: K,
+        b: K,
+        offset: G,
+    ) -> Result<(), PotentialError<G, <K::Value as UnifyValue>::Error>> {
+        let (root_a, offset_a) = self.find_with_offset(a);
+        let (root_b, offset_b) = self.find_with_offset(b);
+
+        if root_a == root_b {
+            let existing = offset_a.compose(&offset_b.inverse());
+            if existing == offset {
+                return Ok(());
+            }
+            return Err(PotentialError::Inconsistent {
+                existing,
+                requested: offset,
+            });
+        }
+
+        let value_a = &self.values[root_a.index().as_usize()].value;
+        let value_b = &self.values[root_b.index().as_usize()].value;
+        let combined = UnifyValue::unify_values(value_a, value_b).map_err(PotentialError::Value)?;
+
+        // With x_a = A(x_ra) and x_b = B(x_rb), the new fact gives
+        // x_ra = (A^-1 . g . B)(x_rb).
+        let root_offset = offset_a.inverse().compose(&offset).compose(&offset_b);
+        let rank_a = self.values[root_a.index().as_usize()].rank;
+        let rank_b = self.values[root_b.index().as_usize()].rank;
+        if rank_a > rank_b {
+            self.redirect_root(root_b, root_a, root_offset.inverse(), rank_a, combined);
+        } else {
+            let new_rank = if rank_a == rank_b { rank_b + 1 } else { rank_b };
+            self.redirect_root(root_a, root_b, root_offset, new_rank, combined);
+        }
+        Ok(())
+    }
+
+    /// Starts a new snapshot. Each snapshot must be either
+    /// rolled back or committed in a "LIFO" (stack) order.
+    pub fn snapshot(&mut self) -> PotentialSnapshot {
+        PotentialSnapshot {
+            snapshot: self.values.start_snapshot(),
+        }
+    }
+
+    /// Reverses all changes since the last snapshot. Also
+    /// removes any keys that have been created since then.
+    pub fn rollback_to(&mut self, snapshot: PotentialSnapshot) {
+        debug!("{}: rollback_to()", K::tag());
+        self.values.rollback_to(snapshot.snapshot);
+    }
+
+    /// Commits all changes since the last snapshot. Of course, they
+    /// can still be undone if there is a snapshot further out.
+    pub fn commit(&mut self, snapshot: PotentialSnapshot) {
+        debug!("{}: commit()", K::tag());
+        self.values.commit(snapshot.snapshot);
+    }
+
+    /// Returns the root of `key` along with the offset `g` such that
+    /// `x_key = g(x_root)`, compressing the path along the way.
+    fn find_with_offset(&mut self, key: K) -> (K, G) {
+        let mut path = Vec::new();
+        let mut current = key;
+        loop {
+            let parent = self.values[current.index().as_usize()].parent;
+            if parent == current {
+                break;
+            }
+            path.push(current);
+            current = parent;
+        }
+
+        // Walk back down from the root, composing offsets, and point
+        // every key on the path directly at the root.
+        let root = current;
+        let mut to_root = G::identity();
+        for &key in path.iter().rev() {
+            let index = key.index().as_usize();
+            to_root = self.values[index].offset.compose(&to_root);
+            if self.values[index].parent != root {
+                let offset = to_root.clone();
+                self.values.update(index, |node| {
+                    node.parent = root;
+                    node.offset = offset;
+                });
+            }
+        }
+        (root, to_root)
+    }
+
+    /// Redirects `old_root` to `new_root` with the given offset
+    /// (`x_old_root = offset(x_new_root)`), and updates the rank and
+    /// value of `new_root`.
+    fn redirect_root(&mut self, old_root: K, new_root: K, offset: G, rank: u32, value: K::Value) {
+        debug!("{}: redirect {:?} to {:?}", K::tag(), old_root, new_root);
+        self.values.update(old_root.index().as_usize(), |node| {
+            node.parent = new_root;
+            node.offset = offset;
+        });
+        self.values.update(new_root.index().as_usize(), |node| {
+            node.rank = rank;
+            node.value = value;
+        });
+    }
+}
@@ -0,0 +1,301 @@
+//! A backing store for keys whose indices are sparse, such as hashes
+//! of AST nodes, rather than the dense `0..n` of `InPlace`. The values
+//! of the keys are kept in order of creation, and a hash map finds the
+//! position of each key from its index.
+
+use alloc::vec::Vec;
+use core::mem;
+use core::ops::{self, Range};
+use hashbrown::HashMap;
+
+use invariants::InvariantError;
+use undo_log::{self, Rollback, Snapshots, UndoLogs, VecLog};
+
+use super::backing_vec::CompressPath;
+use super::{assert_index_not_reserved, UnifyIndex, UnifyKey, VarValue};
+use super::{UnificationStore, UnificationStoreBase, UnificationStoreMut};
+
+/// Backing store for a unification table whose keys are sparse. Keys
+/// are created on first touch: `find`, `union`, `unify_var_var`,
+/// `probe_value` and the other methods of the table that take `&mut
+/// self` create a key they have not seen before as a class of its
+/// own, with the value `Default::default()`. `new_key` creates keys
+/// with increasing indices, starting from zero and skipping the indices
+/// in use; rolling back a snapshot also rolls back the next index.
+///
+/// # Panics
+///
+/// As with any table, the largest value of the index type is reserved,
+/// so touching the key with that index panics. Methods that take
+/// `&self`, such as `find_shared` or `members`, panic on keys that have
+/// not been created yet. `vars_since_snapshot` and `canonicalize`
+/// describe keys by ranges of indices, so they panic unless the keys
+/// involved form such a range.
+///
+/// ```
+/// #[macro_use]
+/// extern crate ena;
+///
+/// use ena::unify::{SparseUnificationTable, UnificationTable};
+///
+/// unify_key! {
+///     struct AstId {
+///         value: (),
+///         tag: "AstId",
+///     }
+/// }
+///
+/// fn main() {
+///     let mut table: SparseUnificationTable<AstId> = UnificationTable::new();
+///     table.union(0xdead_beef, 0x0bad_f00d);
+///     assert!(table.unioned(0x0bad_f00d, 0xdead_beef));
+///     assert!(!table.unioned(0xdead_beef, 7));
+///     assert_eq!(table.len(), 3);
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Sparse<K: UnifyKey> {
+    keys: Keys<K>,
+    undo_log: VecLog<UndoLog<K>>,
+
+    /// No key has this index, and `new_key` uses it next. It only
+    /// moves forward, except when a snapshot is rolled back.
+    next_index: usize,
+}
+
+#[derive(Clone, Debug)]
+struct Keys<K: UnifyKey> {
+    /// The keys, in order of creation.
+    keys: Vec<K>,
+
+    /// The value of each key in `keys`, at the same position.
+    values: Vec<VarValue<K>>,
+
+    /// Maps the index of each key to its position in `keys`.
+    positions: HashMap<usize, usize>,
+}
+
+#[derive(Clone, Debug)]
+enum UndoLog<K: UnifyKey> {
+    /// A new key was pushed.
+    NewKey,
+
+    /// The key at the given position had the given value.
+    SetKey(usize, VarValue<K>),
+
+    /// Path compression redirected the keys at the given positions,
+    /// which had the given parents.
+    RestoreParents(Vec<(usize, K)>),
+}
+
+/// A snapshot of a `Sparse` store; see `UnificationTable::snapshot`.
+pub struct SparseSnapshot {
+    len: usize,
+    next_index: usize,
+    snapshot: undo_log::Snapshot,
+}
+
+// A manual impl avoids a `Default` bound on `K`.
+impl<K: UnifyKey> Default for Sparse<K> {
+    fn default() -> Self {
+        Sparse {
+            keys: Keys {
+                keys: Vec::new(),
+                values: Vec::new(),
+                positions: HashMap::new(),
+            },
+            undo_log: VecLog::default(),
+            next_index: 0,
+        }
+    }
+}
+
+impl<K: UnifyKey> Sparse<K> {
+    fn position(&self, index: usize) -> usize {
+        match self.keys.positions.get(&index) {
+            Some(&position) => position,
+            None => panic!("{}: no key with index {}", K::tag(), index),
+        }
+    }
+
+    fn log(&mut self, undo: UndoLog<K>) {
+        if self.undo_log.in_snapshot() {
+            self.undo_log.push(undo);
+        }
+    }
+}
+
+impl<K: UnifyKey> Rollback<UndoLog<K>> for Keys<K> {
+    fn reverse(&mut self, undo: UndoLog<K>) {
+        match undo {
+            UndoLog::NewKey => {
+                let key = self.keys.pop().unwrap();
+                self.values.pop();
+                self.positions.remove(&key.index().as_usize());
+            }
+            UndoLog::SetKey(position, value) => {
+                self.values[position] = value;
+            }
+            UndoLog::RestoreParents(old_parents) => {
+                for (position, parent) in old_parents.into_iter().rev() {
+                    self.values[position].parent = parent;
+                }
+            }
+        }
+    }
+}
+
+impl<K: UnifyKey> UnificationStoreBase for Sparse<K> {
+    type Key = K;
+    type Value = K::Value;
+
+    fn len(&self) -> usize {
+        self.keys.keys.len()
+    }
+
+    fn index_at(&self, position: usize) -> usize {
+        self.keys.keys[position].index().as_usize()
+    }
+
+    fn position_of(&self, index: usize) -> Option<usize> {
+        self.keys.positions.get(&index).cloned()
+    }
+
+    fn next_index(&self) -> usize {
+        self.next_index
+    }
+}
+
+impl<K> UnificationStoreMut for Sparse<K>
+where
+    K: UnifyKey,
+    K::Value: Default,
+{
+    fn reset_unifications(&mut self, mut value: impl FnMut(K::Index) -> VarValue<Self::Key>) {
+        for position in 0..self.len() {
+            let new_value = value(self.keys.keys[position].index());
+            let old_value = mem::replace(&mut self.keys.values[position], new_value);
+            self.log(UndoLog::SetKey(position, old_value));
+        }
+    }
+
+    fn push(&mut self, value: VarValue<Self::Key>) {
+        // A new key is a root, so it is its own parent.
+        let key = value.parent;
+        let position = self.len();
+        let previous = self.keys.positions.insert(key.index().as_usize(), position);
+        assert!(
+            previous.is_none(),
+            "{}: key {:?} already exists",
+            K::tag(),
+            key
+        );
+        self.keys.keys.push(key);
+        self.keys.values.push(value);
+        self.log(UndoLog::NewKey);
+        while self.keys.positions.contains_key(&self.next_index) {
+            self.next_index += 1;
+        }
+    }
+
+    fn reserve(&mut self, num_new_values: usize) {
+        self.keys.keys.reserve(num_new_values);
+        self.keys.values.reserve(num_new_values);
+        self.keys.positions.reserve(num_new_values);
+    }
+
+    fn update<F>(&mut self, index: usize, op: F)
+    where
+        F: FnOnce(&mut VarValue<Self::Key>),
+    {
+        let position = self.position(index);
+        if self.undo_log.in_snapshot() {
+            let old_value = self.keys.values[position].clone();
+            self.undo_log.push(UndoLog::SetKey(position, old_value));
+        }
+        op(&mut self.keys.values[position]);
+    }
+
+    fn touch(&mut self, key: Self::Key, weight: K::Index) -> bool {
+        let index = key.index().as_usize();
+        if self.keys.positions.contains_key(&index) {
+            return false;
+        }
+        assert_index_not_reserved::<K>(index);
+        self.push(VarValue::new_var(key, K::Value::default(), weight));
+        true
+    }
+
+    fn check_invariants(&self) -> Result<(), InvariantError> {
+        self.undo_log.check_invariants()
+    }
+
+    fn compress_path(&mut self, path: CompressPath<Self::Key>) {
+        let in_snapshot = self.undo_log.in_snapshot();
+        let mut old_parents = Vec::new();
+        path.run(self, |sparse, index, parent| {
+            let position = sparse.position(index);
+            let old_parent = mem::replace(&mut sparse.keys.values[position].parent, parent);
+            if in_snapshot {
+                old_parents.push((position, old_parent));
+            }
+        });
+        if in_snapshot {
+            self.undo_log.push(UndoLog::RestoreParents(old_parents));
+        }
+    }
+}
+
+impl<K> UnificationStore for Sparse<K>
+where
+    K: UnifyKey,
+    K::Value: Default,
+{
+    type Snapshot = SparseSnapshot;
+
+    fn start_snapshot(&mut self) -> SparseSnapshot {
+        SparseSnapshot {
+            len: self.len(),
+            next_index: self.next_index,
+            snapshot: self.undo_log.start_snapshot(),
+        }
+    }
+
+    fn rollback_to(&mut self, snapshot: SparseSnapshot) {
+        let keys = &mut self.keys;
+        self.undo_log.rollback_to(|| keys, snapshot.snapshot);
+        self.next_index = snapshot.next_index;
+    }
+
+    fn commit(&mut self, snapshot: SparseSnapshot) {
+        self.undo_log.commit(snapshot.snapshot);
+    }
+
+    /// Returns the indices of the keys created since `snapshot`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if these keys do not have consecutive indices, in order
+    /// of creation.
+    fn values_since_snapshot(&self, snapshot: &SparseSnapshot) -> Range<usize> {
+        if snapshot.len == self.len() {
+            return self.len()..self.len();
+        }
+        let start = self.index_at(snapshot.len);
+        for position in snapshot.len..self.len() {
+            assert!(
+                self.index_at(position) == start + (position - snapshot.len),
+                "{}: the keys created since the snapshot are not a range",
+                K::tag()
+            );
+        }
+        start..start + (self.len() - snapshot.len)
+    }
+}
+
+impl<K: UnifyKey> ops::Index<usize> for Sparse<K> {
+    type Output = VarValue<K>;
+    fn index(&self, index: usize) -> &VarValue<K> {
+        &self.keys.values[self.position(index)]
+    }
+}
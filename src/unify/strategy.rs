@@ -0,0 +1,111 @@
+//! Strategies for choosing the new root when two classes are unioned.
+//!
+//! Each root carries a *weight* whose meaning is defined by the
+//! strategy: an upper bound on the height of its tree for `ByRank` and
+//! `ByIndex`, the number of keys in its class for `BySize`. All of
+//! the strategies provided here hang the lighter tree below the
+//! heavier one, which keeps trees logarithmically shallow and thus
+//! preserves the usual near-constant amortized running time.
+//!
+//! Weights have the index type of the keys: a class cannot have more
+//! keys than there are indices, and ranks are much smaller still.
+
+use core::fmt::Debug;
+
+use super::{UnifyIndex, UnifyKey};
+
+/// Decides which of two roots becomes the root of their union, and
+/// maintains the weight of the resulting root. This is consulted only
+/// if `UnifyKey::order_roots` returns `None`.
+pub trait UnionStrategy: Clone + Debug + Default {
+    /// The weight of a freshly created key.
+    fn initial_weight<I: UnifyIndex>() -> I;
+
+    /// Given two distinct roots and their weights, returns the key that
+    /// should become the new root, followed by the key that should be
+    /// redirected to it.
+    fn order_roots<K: UnifyKey>(a: K, weight_a: K::Index, b: K, weight_b: K::Index) -> (K, K);
+
+    /// Returns the weight of a root of weight `root_weight` after a root
+    /// of weight `child_weight` has been redirected to it.
+    fn merged_weight<I: UnifyIndex>(root_weight: I, child_weight: I) -> I;
+}
+
+/// Union by rank: the root whose tree may be taller wins. If the
+/// ranks are equal, the second key becomes the root. This is the
+/// default strategy.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ByRank;
+
+impl UnionStrategy for ByRank {
+    fn initial_weight<I: UnifyIndex>() -> I {
+        I::from_usize(0)
+    }
+
+    fn order_roots<K: UnifyKey>(a: K, rank_a: K::Index, b: K, rank_b: K::Index) -> (K, K) {
+        if rank_a > rank_b {
+            (a, b)
+        } else {
+            (b, a)
+        }
+    }
+
+    fn merged_weight<I: UnifyIndex>(root_rank: I, child_rank: I) -> I {
+        if root_rank > child_rank {
+            root_rank
+        } else {
+            child_rank.saturating_add(I::from_usize(1))
+        }
+    }
+}
+
+/// Union by size: the root of the larger class wins. If the sizes are
+/// equal, the second key becomes the root.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BySize;
+
+impl UnionStrategy for BySize {
+    fn initial_weight<I: UnifyIndex>() -> I {
+        I::from_usize(1)
+    }
+
+    fn order_roots<K: UnifyKey>(a: K, size_a: K::Index, b: K, size_b: K::Index) -> (K, K) {
+        if size_a > size_b {
+            (a, b)
+        } else {
+            (b, a)
+        }
+    }
+
+    fn merged_weight<I: UnifyIndex>(root_size: I, child_size: I) -> I {
+        root_size.saturating_add(child_size)
+    }
+}
+
+/// Union by rank, with ties broken in favor of the key with the lowest
+/// index. Whenever the lower key can become the root without making
+/// the tree taller than union by rank would, it does.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ByIndex;
+
+impl UnionStrategy for ByIndex {
+    fn initial_weight<I: UnifyIndex>() -> I {
+        ByRank::initial_weight()
+    }
+
+    fn order_roots<K: UnifyKey>(a: K, rank_a: K::Index, b: K, rank_b: K::Index) -> (K, K) {
+        if rank_a == rank_b {
+            if a.index() < b.index() {
+                (a, b)
+            } else {
+                (b, a)
+            }
+        } else {
+            ByRank::order_roots(a, rank_a, b, rank_b)
+        }
+    }
+
+    fn merged_weight<I: UnifyIndex>(root_rank: I, child_rank: I) -> I {
+        ByRank::merged_weight(root_rank, child_rank)
+    }
+}
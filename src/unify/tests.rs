@@ -17,24 +17,23 @@ extern crate test;
 #[cfg(feature = "bench")]
 use self::test::Bencher;
 use std::cmp;
+use std::println;
+use std::string::{String, ToString};
+use std::vec::Vec;
 #[cfg(feature = "persistent")]
 use unify::Persistent;
-use unify::{EqUnifyValue, InPlace, InPlaceUnificationTable, NoError, UnifyKey, UnifyValue};
-use unify::{UnificationStore, UnificationTable};
-
-#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
-struct UnitKey(u32);
-
-impl UnifyKey for UnitKey {
-    type Value = ();
-    fn index(&self) -> u32 {
-        self.0
-    }
-    fn from_index(u: u32) -> UnitKey {
-        UnitKey(u)
-    }
-    fn tag() -> &'static str {
-        "UnitKey"
+use unify::{Bounds, BoundsError, LatticeValue};
+use unify::{
+    ByIndex, BySize, EqUnifyValue, InPlace, NoError, PathCompression, UnifyKey, UnifyValue,
+};
+use unify::{ByRank, InPlaceUnificationTable, UnificationObserver, UnifyError, UnifyValueError};
+use unify::{ExplainingUnificationTable, UnificationStore, UnificationTable, UnionStrategy};
+use unify::{Group, PotentialError, PotentialUnificationTable};
+
+unify_key! {
+    struct UnitKey {
+        value: (),
+        tag: "UnitKey",
     }
 }
 
@@ -60,9 +59,9 @@ fn basic() {
             let mut ut: UnificationTable<S> = UnificationTable::new();
             let k1 = ut.new_key(());
             let k2 = ut.new_key(());
-            assert_eq!(ut.unioned(k1, k2), false);
+            assert!(!ut.unioned(k1, k2));
             ut.union(k1, k2);
-            assert_eq!(ut.unioned(k1, k2), true);
+            assert!(ut.unioned(k1, k2));
         }
     }
 }
@@ -92,6 +91,65 @@ fn big_array() {
     }
 }
 
+#[test]
+fn big_array_other_compressions() {
+    all_modes! {
+        S for UnitKey => {
+            for &compression in &[PathCompression::Halving, PathCompression::Splitting] {
+                let mut ut: UnificationTable<S> = UnificationTable::new();
+                ut.set_path_compression(compression);
+                let mut keys = Vec::new();
+                const MAX: usize = 1 << 15;
+
+                for _ in 0..MAX {
+                    keys.push(ut.new_key(()));
+                }
+
+                for i in 1..MAX {
+                    let l = keys[i - 1];
+                    let r = keys[i];
+                    ut.union(l, r);
+                }
+
+                for i in 0..MAX {
+                    assert!(ut.unioned(keys[0], keys[i]));
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn big_array_other_strategies() {
+    fn run<S: Default + UnificationStore<Key = UnitKey, Value = ()>, U: UnionStrategy>() {
+        let mut ut: UnificationTable<S, U> = UnificationTable::new();
+        let mut keys = Vec::new();
+        const MAX: usize = 1 << 15;
+
+        for _ in 0..MAX {
+            keys.push(ut.new_key(()));
+        }
+
+        for i in 1..MAX {
+            let l = keys[i - 1];
+            let r = keys[i];
+            ut.union(l, r);
+        }
+
+        for i in 0..MAX {
+            assert!(ut.unioned(keys[0], keys[i]));
+        }
+    }
+
+    run::<InPlace<UnitKey>, BySize>();
+    run::<InPlace<UnitKey>, ByIndex>();
+
+    #[cfg(feature = "persistent")]
+    run::<Persistent<UnitKey>, BySize>();
+    #[cfg(feature = "persistent")]
+    run::<Persistent<UnitKey>, ByIndex>();
+}
+
 #[cfg(feature = "bench")]
 fn big_array_bench_generic<S: Default + UnificationStore<Key = UnitKey, Value = ()>>(
     b: &mut Bencher,
@@ -123,6 +181,45 @@ fn big_array_bench_InPlace(b: &mut Bencher) {
     big_array_bench_generic::<InPlace<UnitKey>>(b);
 }
 
+#[cfg(feature = "bench")]
+fn big_array_bench_compression_generic<S: Default + UnificationStore<Key = UnitKey, Value = ()>>(
+    b: &mut Bencher,
+    compression: PathCompression,
+) {
+    let mut ut: UnificationTable<S> = UnificationTable::new();
+    ut.set_path_compression(compression);
+    let mut keys = Vec::new();
+    const MAX: usize = 1 << 15;
+
+    for _ in 0..MAX {
+        keys.push(ut.new_key(()));
+    }
+
+    b.iter(|| {
+        for i in 1..MAX {
+            let l = keys[i - 1];
+            let r = keys[i];
+            ut.union(l, r);
+        }
+
+        for i in 0..MAX {
+            assert!(ut.unioned(keys[0], keys[i]));
+        }
+    })
+}
+
+#[cfg(feature = "bench")]
+#[bench]
+fn big_array_bench_halving_InPlace(b: &mut Bencher) {
+    big_array_bench_compression_generic::<InPlace<UnitKey>>(b, PathCompression::Halving);
+}
+
+#[cfg(feature = "bench")]
+#[bench]
+fn big_array_bench_splitting_InPlace(b: &mut Bencher) {
+    big_array_bench_compression_generic::<InPlace<UnitKey>>(b, PathCompression::Splitting);
+}
+
 #[cfg(all(feature = "bench", feature = "persistent"))]
 #[bench]
 fn big_array_bench_Persistent(b: &mut Bencher) {
@@ -241,19 +338,10 @@ fn even_odd() {
     }
 }
 
-#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
-struct IntKey(u32);
-
-impl UnifyKey for IntKey {
-    type Value = Option<i32>;
-    fn index(&self) -> u32 {
-        self.0
-    }
-    fn from_index(u: u32) -> IntKey {
-        IntKey(u)
-    }
-    fn tag() -> &'static str {
-        "IntKey"
+unify_key! {
+    struct IntKey {
+        value: Option<i32>,
+        tag: "IntKey",
     }
 }
 
@@ -362,37 +450,30 @@ fn unify_root_value_2() {
     }
 }
 
-#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
-struct OrderedKey(u32);
+unify_key! {
+    struct OrderedKey {
+        value: OrderedRank,
+        tag: "OrderedKey",
+        order_roots: order_by_rank,
+    }
+}
 
 #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
 struct OrderedRank(u32);
 
-impl UnifyKey for OrderedKey {
-    type Value = OrderedRank;
-    fn index(&self) -> u32 {
-        self.0
-    }
-    fn from_index(u: u32) -> OrderedKey {
-        OrderedKey(u)
-    }
-    fn tag() -> &'static str {
-        "OrderedKey"
-    }
-    fn order_roots(
-        a: OrderedKey,
-        a_rank: &OrderedRank,
-        b: OrderedKey,
-        b_rank: &OrderedRank,
-    ) -> Option<(OrderedKey, OrderedKey)> {
-        println!("{:?} vs {:?}", a_rank, b_rank);
-        if a_rank > b_rank {
-            Some((a, b))
-        } else if b_rank > a_rank {
-            Some((b, a))
-        } else {
-            None
-        }
+fn order_by_rank(
+    a: OrderedKey,
+    a_rank: &OrderedRank,
+    b: OrderedKey,
+    b_rank: &OrderedRank,
+) -> Option<(OrderedKey, OrderedKey)> {
+    println!("{:?} vs {:?}", a_rank, b_rank);
+    if a_rank > b_rank {
+        Some((a, b))
+    } else if b_rank > a_rank {
+        Some((b, a))
+    } else {
+        None
     }
 }
 
@@ -424,7 +505,7 @@ fn ordered_key() {
             ut.union(k0_5, k0_6); // rank of new root now 1
 
             ut.union(k0_1, k0_5); // new root rank 2, should not be k0_5 or k0_6
-            assert!(vec![k0_1, k0_2, k0_3, k0_4].contains(&ut.find(k0_1)));
+            assert!([k0_1, k0_2, k0_3, k0_4].contains(&ut.find(k0_1)));
         }
     }
 }
@@ -432,8 +513,8 @@ fn ordered_key() {
 #[test]
 fn ordered_key_k1() {
     all_modes! {
-        S for UnitKey => {
-            let mut ut: InPlaceUnificationTable<OrderedKey> = UnificationTable::new();
+        S for OrderedKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
 
             let k0_1 = ut.new_key(OrderedRank(0));
             let k0_2 = ut.new_key(OrderedRank(0));
@@ -450,7 +531,7 @@ fn ordered_key_k1() {
 
             ut.union(k0_1, k1_5); // even though k1 has lower rank, it wins
             assert!(
-                vec![k1_5, k1_6].contains(&ut.find(k0_1)),
+                [k1_5, k1_6].contains(&ut.find(k0_1)),
                 "unexpected choice for root: {:?}",
                 ut.find(k0_1)
             );
@@ -484,3 +565,1325 @@ fn clone_table() {
         }
     }
 }
+
+#[test]
+fn members() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let keys: Vec<UnitKey> = (0..6).map(|_| ut.new_key(())).collect();
+            assert_eq!(ut.members(keys[0]).collect::<Vec<_>>(), vec![keys[0]]);
+
+            ut.union(keys[0], keys[1]);
+            ut.union(keys[2], keys[3]);
+            ut.union(keys[3], keys[1]);
+            ut.union(keys[4], keys[5]);
+
+            for &key in &keys[..4] {
+                let mut members: Vec<_> = ut.members(key).collect();
+                assert_eq!(members[0], key);
+                members.sort_by_key(|k| k.0);
+                assert_eq!(members, &keys[..4]);
+            }
+            let mut members: Vec<_> = ut.members(keys[5]).collect();
+            members.sort_by_key(|k| k.0);
+            assert_eq!(members, &keys[4..]);
+        }
+    }
+}
+
+#[test]
+fn members_after_rollback() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k0 = ut.new_key(());
+            let k1 = ut.new_key(());
+            let k2 = ut.new_key(());
+            ut.union(k0, k1);
+
+            let snapshot = ut.snapshot();
+            let k3 = ut.new_key(());
+            ut.union(k2, k3);
+            ut.union(k1, k2);
+            assert_eq!(ut.members(k0).count(), 4);
+            ut.rollback_to(snapshot);
+
+            let mut members: Vec<_> = ut.members(k1).collect();
+            members.sort_by_key(|k| k.0);
+            assert_eq!(members, vec![k0, k1]);
+            assert_eq!(ut.members(k2).collect::<Vec<_>>(), vec![k2]);
+        }
+    }
+}
+
+#[test]
+fn roots_and_classes() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            assert_eq!(ut.num_classes(), 0);
+            let keys: Vec<IntKey> = (0..5).map(|_| ut.new_key(None)).collect();
+            assert_eq!(ut.num_classes(), 5);
+
+            ut.unify_var_var(keys[0], keys[1]).unwrap();
+            ut.unify_var_var(keys[2], keys[3]).unwrap();
+            ut.unify_var_var(keys[1], keys[3]).unwrap();
+            ut.unify_var_var(keys[0], keys[2]).unwrap();
+            ut.unify_var_value(keys[0], Some(7)).unwrap();
+            assert_eq!(ut.num_classes(), 2);
+            assert_eq!(ut.roots().count(), 2);
+
+            for (root, value, members) in ut.classes() {
+                let mut members: Vec<_> = members.collect();
+                assert_eq!(members[0], root);
+                members.sort_by_key(|k| k.0);
+                if members.len() == 4 {
+                    assert_eq!(members, &keys[..4]);
+                    assert_eq!(*value, Some(7));
+                } else {
+                    assert_eq!(members, vec![keys[4]]);
+                    assert_eq!(*value, None);
+                }
+            }
+
+            ut.reset_unifications(|_| None);
+            assert_eq!(ut.num_classes(), 5);
+            assert_eq!(ut.roots().collect::<Vec<_>>(), keys);
+        }
+    }
+}
+
+#[test]
+fn canonicalize() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let keys: Vec<UnitKey> = (0..6).map(|_| ut.new_key(())).collect();
+            ut.union(keys[5], keys[1]);
+            ut.union(keys[4], keys[2]);
+            ut.union(keys[2], keys[5]);
+
+            let canonical = ut.canonicalize();
+            assert_eq!(canonical.num_classes(), 3);
+            let ids: Vec<u32> = keys.iter().map(|&k| canonical.class_of(k)).collect();
+            assert_eq!(ids, [0, 1, 1, 2, 1, 1]);
+            assert_eq!(canonical.members(0), &[keys[0]]);
+            assert_eq!(canonical.members(1), &[keys[1], keys[2], keys[4], keys[5]]);
+            assert_eq!(canonical.representative(2), keys[3]);
+
+            // Every path has been flattened.
+            for &key in &keys {
+                assert_eq!(ut.values[key.index() as usize].parent, ut.find_shared(key));
+            }
+        }
+    }
+}
+
+#[test]
+fn canonicalize_ignores_union_order() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut1: UnificationTable<S> = UnificationTable::new();
+            let mut ut2: UnificationTable<S> = UnificationTable::new();
+            let keys: Vec<UnitKey> = (0..5).map(|_| ut1.new_key(())).collect();
+            for _ in 0..5 {
+                ut2.new_key(());
+            }
+            ut1.union(keys[0], keys[3]);
+            ut1.union(keys[3], keys[4]);
+            ut2.union(keys[4], keys[3]);
+            ut2.union(keys[0], keys[4]);
+            assert_eq!(ut1.canonicalize(), ut2.canonicalize());
+        }
+    }
+}
+
+#[test]
+fn absorb() {
+    all_modes! {
+        S for IntKey => {
+            let mut local: UnificationTable<S> = UnificationTable::new();
+            let l0 = local.new_key(None);
+            let l1 = local.new_key(None);
+            local.new_key(Some(5));
+            local.unify_var_var(l0, l1).unwrap();
+
+            // Local key `i` corresponds to global key `i + 1`.
+            let mut global: UnificationTable<S> = UnificationTable::new();
+            let keys: Vec<IntKey> = (0..4).map(|_| global.new_key(None)).collect();
+            global.unify_var_var(keys[0], keys[3]).unwrap();
+            global.absorb(&local, |k| IntKey(k.0 + 1)).unwrap();
+
+            assert!(global.unioned(keys[1], keys[2]));
+            assert!(!global.unioned(keys[0], keys[1]));
+            assert!(global.unioned(keys[0], keys[3]));
+            assert_eq!(global.probe_value(keys[3]), Some(5));
+            assert_eq!(global.num_classes(), 2);
+        }
+    }
+}
+
+#[test]
+fn absorb_conflict_has_no_effect() {
+    all_modes! {
+        S for IntKey => {
+            let mut local: UnificationTable<S> = UnificationTable::new();
+            let l0 = local.new_key(None);
+            let l1 = local.new_key(Some(1));
+            local.new_key(Some(2));
+            local.unify_var_var(l0, l1).unwrap();
+
+            let mut global: UnificationTable<S> = UnificationTable::new();
+            let g0 = global.new_key(None);
+            let g1 = global.new_key(None);
+            let g2 = global.new_key(None);
+            global.unify_var_var(g0, g1).unwrap();
+
+            // Both local classes land in the same global class, so their
+            // values conflict.
+            assert_eq!(global.absorb(&local, |k| IntKey(k.0 % 2)), Err((1, 2)));
+            assert_eq!(global.probe_value(g0), None);
+            assert_eq!(global.num_classes(), 2);
+
+            let snapshot = global.snapshot();
+            global.absorb(&local, |k| IntKey(k.0 / 2 * 2)).unwrap();
+            assert_eq!(global.probe_value(g1), Some(1));
+            assert_eq!(global.probe_value(g2), Some(2));
+            global.rollback_to(snapshot);
+            assert_eq!(global.probe_value(g0), None);
+            assert_eq!(global.probe_value(g2), None);
+        }
+    }
+}
+
+/// Records the events of a table of `IntKey`s.
+#[derive(Clone, Debug, Default)]
+struct Recorder {
+    events: Vec<String>,
+}
+
+impl UnificationObserver<IntKey> for Recorder {
+    fn on_union(
+        &mut self,
+        new_root: IntKey,
+        old_root: IntKey,
+        old_values: (&Option<i32>, &Option<i32>),
+        new_value: &Option<i32>,
+    ) {
+        self.events.push(format!(
+            "union {} <- {}: {:?} + {:?} = {:?}",
+            new_root.0, old_root.0, old_values.0, old_values.1, new_value
+        ));
+    }
+
+    fn on_value_change(&mut self, root: IntKey, old_value: &Option<i32>, new_value: &Option<i32>) {
+        self.events.push(format!(
+            "value {}: {:?} -> {:?}",
+            root.0, old_value, new_value
+        ));
+    }
+}
+
+#[test]
+fn observer() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S, ByRank, Recorder> = UnificationTable::new();
+            let k0 = ut.new_key(None);
+            let k1 = ut.new_key(Some(1));
+            let k2 = ut.new_key(None);
+            ut.unify_var_var(k0, k1).unwrap();
+            ut.unify_var_value(k2, Some(2)).unwrap();
+            assert!(ut.unify_var_var(k2, k0).is_err());
+            ut.unify_var_var(k0, k1).unwrap();
+            assert_eq!(
+                ut.observer().events,
+                [
+                    "union 1 <- 0: Some(1) + None = Some(1)",
+                    "value 2: None -> Some(2)",
+                ]
+            );
+            ut.observer_mut().events.clear();
+
+            // Rolling back does not fire any events.
+            let snapshot = ut.snapshot();
+            ut.unify_var_value(k2, Some(2)).unwrap();
+            let k3 = ut.new_key(None);
+            ut.unify_var_var(k3, k2).unwrap();
+            ut.rollback_to(snapshot);
+            assert_eq!(
+                ut.observer().events,
+                [
+                    "value 2: Some(2) -> Some(2)",
+                    "union 2 <- 3: Some(2) + None = Some(2)",
+                ]
+            );
+        }
+    }
+}
+
+/// Records the events of a table of `IntKey`s, forgetting those of the
+/// snapshots that are rolled back.
+#[derive(Clone, Debug, Default)]
+struct SnapshotRecorder {
+    recorder: Recorder,
+    snapshots: Vec<usize>,
+}
+
+impl UnificationObserver<IntKey> for SnapshotRecorder {
+    fn on_union(
+        &mut self,
+        new_root: IntKey,
+        old_root: IntKey,
+        old_values: (&Option<i32>, &Option<i32>),
+        new_value: &Option<i32>,
+    ) {
+        self.recorder
+            .on_union(new_root, old_root, old_values, new_value);
+    }
+
+    fn on_value_change(&mut self, root: IntKey, old_value: &Option<i32>, new_value: &Option<i32>) {
+        self.recorder.on_value_change(root, old_value, new_value);
+    }
+
+    fn on_snapshot(&mut self) {
+        self.snapshots.push(self.recorder.events.len());
+    }
+
+    fn on_rollback_to(&mut self) {
+        let len = self.snapshots.pop().unwrap();
+        self.recorder.events.truncate(len);
+    }
+
+    fn on_commit(&mut self) {
+        self.snapshots.pop().unwrap();
+    }
+}
+
+#[test]
+fn observer_snapshots() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S, ByRank, SnapshotRecorder> = UnificationTable::new();
+            let k0 = ut.new_key(None);
+            let k1 = ut.new_key(None);
+            let k2 = ut.new_key(Some(1));
+            let k3 = ut.new_key(Some(2));
+
+            // The failing pair undoes the unions before it.
+            assert!(ut.unify_all(vec![(k0, k1), (k1, k2), (k2, k3)]).is_err());
+            assert!(ut.observer().recorder.events.is_empty());
+            assert!(ut.unify_all_values(vec![(k0, Some(1)), (k2, Some(3))]).is_err());
+            assert!(ut.observer().recorder.events.is_empty());
+
+            ut.unify_all(vec![(k0, k1)]).unwrap();
+            let snapshot = ut.snapshot();
+            ut.unify_var_var(k1, k2).unwrap();
+            ut.rollback_to(snapshot);
+            assert_eq!(
+                ut.observer().recorder.events,
+                ["union 1 <- 0: None + None = None"]
+            );
+            assert!(ut.observer().snapshots.is_empty());
+        }
+    }
+}
+
+#[test]
+fn detailed_errors() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k0 = ut.new_key(None);
+            let k1 = ut.new_key(Some(1));
+            let k2 = ut.new_key(Some(2));
+            let k3 = ut.new_key(None);
+            ut.unify_var_var(k0, k1).unwrap();
+            ut.unify_var_var(k3, k2).unwrap();
+            let root_a = ut.find(k0);
+            let root_b = ut.find(k3);
+
+            assert_eq!(
+                ut.unify_var_var_detailed(k0, k3),
+                Err(UnifyError {
+                    a_key: k0,
+                    a_root: root_a,
+                    a_value: Some(1),
+                    b_key: k3,
+                    b_root: root_b,
+                    b_value: Some(2),
+                    error: (1, 2),
+                })
+            );
+            assert!(!ut.unioned(k0, k3));
+
+            assert_eq!(
+                ut.unify_var_value_detailed(k3, Some(3)),
+                Err(UnifyValueError {
+                    key: k3,
+                    root: root_b,
+                    root_value: Some(2),
+                    value: Some(3),
+                    error: (2, 3),
+                })
+            );
+            assert_eq!(ut.probe_value(k3), Some(2));
+
+            assert!(ut.unify_var_value_detailed(k3, Some(2)).is_ok());
+            let k4 = ut.new_key(None);
+            assert!(ut.unify_var_var_detailed(k4, k0).is_ok());
+            assert_eq!(ut.probe_value(k4), Some(1));
+        }
+    }
+}
+
+#[test]
+fn unify_all() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let keys: Vec<IntKey> = (0..5).map(|_| ut.new_key(None)).collect();
+            ut.unify_var_value(keys[4], Some(4)).unwrap();
+
+            assert!(ut.unify_all(vec![(keys[0], keys[1]), (keys[1], keys[2])]).is_ok());
+            assert!(ut.unioned(keys[0], keys[2]));
+
+            let values = vec![(keys[3], Some(3)), (keys[0], Some(0)), (keys[2], Some(2))];
+            assert_eq!(ut.unify_all_values(values), Err((0, 2)));
+            assert_eq!(ut.probe_value(keys[3]), None);
+            assert_eq!(ut.probe_value(keys[0]), None);
+
+            let pairs = vec![(keys[3], keys[0]), (keys[3], keys[4]), (keys[2], keys[4])];
+            ut.unify_var_value(keys[0], Some(0)).unwrap();
+            assert_eq!(ut.unify_all(pairs), Err((0, 4)));
+            assert!(!ut.unioned(keys[3], keys[0]));
+            assert!(!ut.unioned(keys[3], keys[4]));
+            assert_eq!(ut.num_classes(), 3);
+        }
+    }
+}
+
+#[test]
+fn unify_all_nested() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let keys: Vec<IntKey> = (0..4).map(|_| ut.new_key(None)).collect();
+
+            let snapshot = ut.snapshot();
+            assert!(ut.unify_all(vec![(keys[0], keys[1])]).is_ok());
+            ut.unify_var_value(keys[2], Some(2)).unwrap();
+            ut.unify_var_value(keys[3], Some(3)).unwrap();
+            assert!(ut.unify_all(vec![(keys[1], keys[2]), (keys[2], keys[3])]).is_err());
+            assert!(ut.unioned(keys[0], keys[1]));
+            assert!(!ut.unioned(keys[1], keys[2]));
+            ut.rollback_to(snapshot);
+
+            assert!(!ut.unioned(keys[0], keys[1]));
+            assert_eq!(ut.probe_value(keys[2]), None);
+            assert_eq!(ut.num_classes(), 4);
+        }
+    }
+}
+
+#[test]
+fn probe_and_commit_if_ok() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k0 = ut.new_key(None);
+            let k1 = ut.new_key(Some(1));
+
+            let value = ut.probe(|ut| {
+                ut.unify_var_var(k0, k1).unwrap();
+                ut.probe_value(k0)
+            });
+            assert_eq!(value, Some(1));
+            assert!(!ut.unioned(k0, k1));
+
+            let result = ut.commit_if_ok(|ut| {
+                ut.unify_var_value(k0, Some(2))?;
+                ut.unify_var_var(k0, k1)
+            });
+            assert_eq!(result, Err((2, 1)));
+            assert_eq!(ut.probe_value(k0), None);
+
+            let result = ut.commit_if_ok(|ut| {
+                let k2 = ut.new_key(None);
+                ut.unify_var_var(k2, k1).map(|()| k2)
+            });
+            let k2 = result.unwrap();
+            assert!(ut.unioned(k1, k2));
+        }
+    }
+}
+
+#[test]
+fn probe_rolls_back_on_panic() {
+    use std::panic::{self, AssertUnwindSafe};
+
+    let mut ut: InPlaceUnificationTable<IntKey> = UnificationTable::new();
+    let k0 = ut.new_key(None);
+    let k1 = ut.new_key(None);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        ut.probe(|ut| {
+            ut.unify_var_var(k0, k1).unwrap();
+            panic!("oops");
+        })
+    }));
+    assert!(result.is_err());
+    assert!(!ut.values.values.in_snapshot());
+    assert!(!ut.unioned(k0, k1));
+    assert_eq!(ut.num_classes(), 2);
+}
+
+#[test]
+fn probe_panic_closes_inner_snapshots() {
+    use std::panic::{self, AssertUnwindSafe};
+
+    let mut ut: InPlaceUnificationTable<IntKey> = UnificationTable::new();
+    let k0 = ut.new_key(None);
+    let k1 = ut.new_key(None);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        ut.probe(|ut| {
+            let _snapshot = ut.snapshot();
+            ut.unify_var_var(k0, k1).unwrap();
+            panic!("oops");
+        })
+    }));
+    assert!(result.is_err());
+    assert!(!ut.values.values.in_snapshot());
+    assert!(!ut.unioned(k0, k1));
+
+    // Outside a snapshot nothing is logged, so the log stays empty.
+    ut.unify_var_var(k0, k1).unwrap();
+    assert!(!ut.values.values.in_snapshot());
+    assert_eq!(ut.check_invariants(), Ok(()));
+}
+
+#[test]
+fn num_classes_after_rollback() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k0 = ut.new_key(());
+            let k1 = ut.new_key(());
+            ut.union(k0, k1);
+
+            let snapshot = ut.snapshot();
+            let k2 = ut.new_key(());
+            let k3 = ut.new_key(());
+            ut.union(k2, k3);
+            ut.union(k1, k2);
+            assert_eq!(ut.num_classes(), 1);
+            ut.rollback_to(snapshot);
+
+            assert_eq!(ut.num_classes(), 1);
+            assert_eq!(ut.classes().count(), 1);
+        }
+    }
+}
+
+#[test]
+fn explain() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: ExplainingUnificationTable<S, &'static str> =
+                ExplainingUnificationTable::new();
+            let keys: Vec<UnitKey> = (0..5).map(|_| ut.new_key(())).collect();
+            ut.union(keys[0], keys[1], "0=1");
+            ut.union(keys[2], keys[3], "2=3");
+            ut.union(keys[1], keys[2], "1=2");
+            ut.union(keys[0], keys[3], "0=3 (redundant)");
+
+            assert_eq!(ut.explain(keys[0], keys[0]), Some(vec![]));
+            assert_eq!(ut.explain(keys[0], keys[1]), Some(vec!["0=1"]));
+            assert_eq!(ut.explain(keys[0], keys[3]), Some(vec!["0=1", "1=2", "2=3"]));
+            assert_eq!(ut.explain(keys[3], keys[0]), Some(vec!["2=3", "1=2", "0=1"]));
+            assert_eq!(ut.explain(keys[0], keys[4]), None);
+        }
+    }
+}
+
+/// Tests that explanations are paths between the keys when classes of
+/// different sizes are merged, whichever proof tree gets re-rooted
+#[test]
+fn explain_uneven_classes() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: ExplainingUnificationTable<S, (usize, usize)> =
+                ExplainingUnificationTable::new();
+            let keys: Vec<UnitKey> = (0..12).map(|_| ut.new_key(())).collect();
+            let unions = [
+                (0, 1), (1, 2), (2, 3), (4, 5), (6, 5), (3, 6),
+                (7, 8), (9, 8), (10, 11), (11, 0), (8, 2),
+            ];
+            for &(a, b) in &unions {
+                ut.union(keys[a], keys[b], (a, b));
+            }
+
+            for a in 0..12 {
+                for b in 0..12 {
+                    let reasons = ut.explain(keys[a], keys[b]).unwrap();
+                    let mut current = a;
+                    for (x, y) in reasons {
+                        current = if current == x {
+                            y
+                        } else {
+                            assert_eq!(current, y);
+                            x
+                        };
+                    }
+                    assert_eq!(current, b);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn explain_after_rollback() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: ExplainingUnificationTable<S, u32> = ExplainingUnificationTable::new();
+            let k0 = ut.new_key(());
+            let k1 = ut.new_key(());
+            let k2 = ut.new_key(());
+            ut.union(k0, k1, 1);
+
+            let snapshot = ut.snapshot();
+            ut.union(k1, k2, 2);
+            ut.union(k2, k0, 3);
+            assert_eq!(ut.explain(k0, k2), Some(vec![1, 2]));
+            ut.rollback_to(snapshot);
+
+            assert_eq!(ut.explain(k0, k2), None);
+            ut.union(k2, k0, 4);
+            assert_eq!(ut.explain(k1, k2), Some(vec![1, 4]));
+        }
+    }
+}
+
+#[test]
+fn find_shared() {
+    all_modes! {
+        S for IntKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k1 = ut.new_key(None);
+            let k2 = ut.new_key(None);
+            let k3 = ut.new_key(None);
+            assert!(ut.unify_var_var(k1, k2).is_ok());
+            assert!(ut.unify_var_var(k2, k3).is_ok());
+            assert!(ut.unify_var_value(k3, Some(7)).is_ok());
+
+            let ut = &ut;
+            let root = ut.find_shared(k1);
+            assert_eq!(ut.find_shared(k2), root);
+            assert_eq!(ut.find_shared(k3), root);
+            assert!(ut.unioned_shared(k1, k3));
+            assert_eq!(ut.probe_value_shared(k1), Some(7));
+        }
+    }
+}
+
+#[test]
+fn union_by_size() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S, BySize> = UnificationTable::new();
+            let keys: Vec<UnitKey> = (0..5).map(|_| ut.new_key(())).collect();
+
+            // A class of three keys...
+            ut.union(keys[0], keys[1]);
+            ut.union(keys[2], keys[1]);
+            let root = ut.find(keys[0]);
+
+            // ...beats a class of two keys, even as the first argument.
+            ut.union(keys[3], keys[4]);
+            ut.union(keys[0], keys[3]);
+            assert_eq!(ut.find(keys[4]), root);
+        }
+    }
+}
+
+#[test]
+fn union_by_index() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S, ByIndex> = UnificationTable::new();
+            let keys: Vec<UnitKey> = (0..6).map(|_| ut.new_key(())).collect();
+
+            // Equal ranks: the lowest index wins.
+            ut.union(keys[3], keys[2]);
+            assert_eq!(ut.find(keys[3]), keys[2]);
+            ut.union(keys[5], keys[4]);
+            ut.union(keys[4], keys[2]);
+            assert_eq!(ut.find(keys[5]), keys[2]);
+
+            // A lower index does not win against a higher rank.
+            ut.union(keys[0], keys[5]);
+            assert_eq!(ut.find(keys[0]), keys[2]);
+        }
+    }
+}
+
+/// Builds a single path of `len` keys, each redirected to the next,
+/// by making every new key outrank all previous ones.
+fn long_chain<S: UnificationStore<Key = OrderedKey, Value = OrderedRank>>(
+    ut: &mut UnificationTable<S>,
+    len: u32,
+) -> Vec<OrderedKey> {
+    let mut keys = vec![ut.new_key(OrderedRank(0))];
+    for i in 1..len {
+        let key = ut.new_key(OrderedRank(i));
+        ut.union(keys[keys.len() - 1], key);
+        keys.push(key);
+    }
+    keys
+}
+
+#[test]
+fn long_chain_does_not_overflow() {
+    all_modes! {
+        S for OrderedKey => {
+            for &compression in &[
+                PathCompression::Full,
+                PathCompression::Halving,
+                PathCompression::Splitting,
+            ] {
+                let mut ut: UnificationTable<S> = UnificationTable::new();
+                ut.set_path_compression(compression);
+                let keys = long_chain(&mut ut, 1 << 17);
+                let root = keys[keys.len() - 1];
+                assert_eq!(ut.find(keys[0]), root);
+                assert_eq!(ut.find(keys[1]), root);
+            }
+        }
+    }
+}
+
+#[test]
+fn compression_in_snapshot_is_one_action() {
+    for &compression in &[
+        PathCompression::Full,
+        PathCompression::Halving,
+        PathCompression::Splitting,
+    ] {
+        let mut ut: UnificationTable<InPlace<OrderedKey>> = UnificationTable::new();
+        ut.set_path_compression(compression);
+        let keys = long_chain(&mut ut, 64);
+        let root = keys[keys.len() - 1];
+
+        let snapshot = ut.snapshot();
+        assert_eq!(ut.find(keys[0]), root);
+        assert_eq!(
+            ut.values
+                .values
+                .actions_since_snapshot(&snapshot.snapshot)
+                .len(),
+            1
+        );
+        ut.rollback_to(snapshot);
+
+        // Rolling back restores the original chain.
+        for i in 0..keys.len() - 1 {
+            assert_eq!(ut.value(keys[i]).parent, keys[i + 1]);
+        }
+    }
+}
+
+#[test]
+fn potential_offsets() {
+    let mut ut: PotentialUnificationTable<UnitKey, i32> = PotentialUnificationTable::new();
+    let a = ut.new_key(());
+    let b = ut.new_key(());
+    let c = ut.new_key(());
+    let d = ut.new_key(());
+
+    // a = b + 3, c = b - 2, d = c + 10
+    assert!(ut.union(a, b, 3).is_ok());
+    assert!(ut.union(c, b, -2).is_ok());
+    assert!(ut.union(d, c, 10).is_ok());
+
+    assert_eq!(ut.offset(a, b), Some(3));
+    assert_eq!(ut.offset(b, a), Some(-3));
+    assert_eq!(ut.offset(a, c), Some(5));
+    assert_eq!(ut.offset(d, a), Some(5));
+    assert_eq!(ut.offset(d, d), Some(0));
+
+    // Consistent facts are accepted; inconsistent ones are not.
+    assert!(ut.union(d, a, 5).is_ok());
+    match ut.union(a, d, 1) {
+        Err(PotentialError::Inconsistent {
+            existing,
+            requested,
+        }) => assert_eq!((existing, requested), (-5, 1)),
+        result => panic!("unexpected result: {:?}", result),
+    }
+
+    let e = ut.new_key(());
+    assert_eq!(ut.offset(a, e), None);
+}
+
+#[test]
+fn potential_parity() {
+    // A triangle is not bipartite; a square is.
+    let mut ut: PotentialUnificationTable<UnitKey, bool> = PotentialUnificationTable::new();
+    let keys: Vec<UnitKey> = (0..4).map(|_| ut.new_key(())).collect();
+    assert!(ut.union(keys[0], keys[1], true).is_ok());
+    assert!(ut.union(keys[1], keys[2], true).is_ok());
+    assert!(ut.union(keys[2], keys[3], true).is_ok());
+    assert!(ut.union(keys[3], keys[0], true).is_ok());
+    assert!(ut.union(keys[0], keys[2], true).is_err());
+    assert_eq!(ut.offset(keys[0], keys[2]), Some(false));
+}
+
+/// The map `x -> scale * x + shift`, where `scale` is 1 or -1.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Affine {
+    scale: i64,
+    shift: i64,
+}
+
+impl Group for Affine {
+    fn identity() -> Self {
+        Affine { scale: 1, shift: 0 }
+    }
+
+    fn compose(&self, other: &Self) -> Self {
+        Affine {
+            scale: self.scale * other.scale,
+            shift: self.scale * other.shift + self.shift,
+        }
+    }
+
+    fn inverse(&self) -> Self {
+        Affine {
+            scale: self.scale,
+            shift: -self.scale * self.shift,
+        }
+    }
+}
+
+#[test]
+fn potential_non_commutative() {
+    let mut ut: PotentialUnificationTable<UnitKey, Affine> = PotentialUnificationTable::new();
+    let keys: Vec<UnitKey> = (0..4).map(|_| ut.new_key(())).collect();
+
+    // k0 = -k1 + 1, k1 = k2 + 2, k3 = -k2
+    let negate_plus_one = Affine {
+        scale: -1,
+        shift: 1,
+    };
+    let plus_two = Affine { scale: 1, shift: 2 };
+    let negate = Affine {
+        scale: -1,
+        shift: 0,
+    };
+    assert!(ut.union(keys[0], keys[1], negate_plus_one).is_ok());
+    assert!(ut.union(keys[1], keys[2], plus_two).is_ok());
+    assert!(ut.union(keys[3], keys[2], negate).is_ok());
+
+    // k0 = -(k2 + 2) + 1 = -k2 - 1 = k3 - 1
+    assert_eq!(
+        ut.offset(keys[0], keys[2]),
+        Some(Affine {
+            scale: -1,
+            shift: -1
+        })
+    );
+    assert_eq!(
+        ut.offset(keys[0], keys[3]),
+        Some(Affine {
+            scale: 1,
+            shift: -1
+        })
+    );
+    assert_eq!(
+        ut.offset(keys[2], keys[0]),
+        Some(Affine {
+            scale: -1,
+            shift: -1
+        })
+    );
+}
+
+#[test]
+fn potential_rollback() {
+    let mut ut: PotentialUnificationTable<IntKey, i64> = PotentialUnificationTable::new();
+    let a = ut.new_key(Some(1));
+    let b = ut.new_key(None);
+    let c = ut.new_key(Some(2));
+    assert!(ut.unify_var_var(a, b, 4).is_ok());
+
+    let snapshot = ut.snapshot();
+    assert!(ut.unify_var_var(b, c, 1).is_err());
+    let d = ut.new_key(None);
+    assert!(ut.unify_var_var(d, b, 7).is_ok());
+    assert_eq!(ut.offset(d, a), Some(3));
+    assert_eq!(ut.probe_value(d), Some(1));
+    ut.rollback_to(snapshot);
+
+    assert_eq!(ut.len(), 3);
+    assert_eq!(ut.offset(b, a), Some(-4));
+    assert!(!ut.unioned(b, c));
+    assert_eq!(
+        ut.unify_var_var(a, c, 0),
+        Err(PotentialError::Value((1, 2)))
+    );
+}
+
+/// Sets of up to eight flags, ordered by inclusion.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Flags(u8);
+
+impl LatticeValue for Flags {
+    fn join(&self, other: &Flags) -> Flags {
+        Flags(self.0 | other.0)
+    }
+
+    fn meet(&self, other: &Flags) -> Flags {
+        Flags(self.0 & other.0)
+    }
+
+    fn le(&self, other: &Flags) -> bool {
+        self.0 & !other.0 == 0
+    }
+}
+
+unify_key! {
+    struct FlagsKey {
+        value: Bounds<Flags>,
+        tag: "FlagsKey",
+    }
+}
+
+#[test]
+fn lattice_bounds() {
+    all_modes! {
+        S for FlagsKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let a = ut.new_key(Bounds::unbounded());
+            let b = ut.new_key(Bounds::at_most(Flags(0b0111)));
+
+            assert!(ut.add_lower_bound(a, Flags(0b0001)).is_ok());
+            assert!(ut.add_lower_bound(a, Flags(0b0010)).is_ok());
+            assert_eq!(ut.probe_value(a).lower(), Some(&Flags(0b0011)));
+
+            assert!(ut.add_upper_bound(a, Flags(0b1011)).is_ok());
+            assert!(ut.unify_var_var(a, b).is_ok());
+            let bounds = ut.probe_value(b);
+            assert_eq!(bounds.lower(), Some(&Flags(0b0011)));
+            assert_eq!(bounds.upper(), Some(&Flags(0b0011)));
+
+            // Incomparable bounds cross, too.
+            let c = ut.new_key(Bounds::at_least(Flags(0b0100)));
+            assert_eq!(
+                ut.unify_var_var(a, c),
+                Err(BoundsError { lower: Flags(0b0111), upper: Flags(0b0011) })
+            );
+            assert!(!ut.unioned(a, c));
+        }
+    }
+}
+
+#[test]
+fn lattice_bounds_rollback() {
+    all_modes! {
+        S for FlagsKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let a = ut.new_key(Bounds::at_least(Flags(0b0001)));
+
+            let snapshot = ut.snapshot();
+            assert!(ut.add_upper_bound(a, Flags(0b0011)).is_ok());
+            assert_eq!(
+                ut.add_upper_bound(a, Flags(0b0010)),
+                Err(BoundsError { lower: Flags(0b0001), upper: Flags(0b0010) })
+            );
+            assert_eq!(ut.probe_value(a).upper(), Some(&Flags(0b0011)));
+            ut.rollback_to(snapshot);
+
+            assert_eq!(ut.probe_value(a), Bounds::at_least(Flags(0b0001)));
+        }
+    }
+}
+
+unify_key! {
+    struct SmallKey {
+        value: (),
+        index: u16,
+        tag: "SmallKey",
+    }
+
+    struct WideKey {
+        value: (),
+        index: u64,
+        tag: "WideKey",
+    }
+}
+
+/// Tests that a table can hold as many keys as the index type allows
+#[test]
+fn narrow_index() {
+    fn run<U: UnionStrategy>() {
+        let mut ut: UnificationTable<InPlace<SmallKey>, U> = UnificationTable::new();
+        let keys: Vec<SmallKey> = (0..u16::MAX).map(|_| ut.new_key(())).collect();
+        assert_eq!(keys[keys.len() - 1].index(), u16::MAX - 1);
+        for i in 1..keys.len() {
+            ut.union(keys[i - 1], keys[i]);
+        }
+        assert_eq!(ut.num_classes(), 1);
+        assert!(ut.unioned(keys[0], keys[keys.len() - 1]));
+
+        let snapshot = ut.snapshot();
+        let end = SmallKey(u16::MAX);
+        assert_eq!(ut.vars_since_snapshot(&snapshot), end..end);
+        ut.commit(snapshot);
+    }
+
+    run::<ByRank>();
+    run::<BySize>();
+    run::<ByIndex>();
+}
+
+#[test]
+#[should_panic(expected = "the largest index 65535 is reserved")]
+fn narrow_index_overflow() {
+    let mut ut: InPlaceUnificationTable<SmallKey> = UnificationTable::new();
+    for _ in 0..u16::MAX {
+        ut.new_key(());
+    }
+    ut.new_key(());
+}
+
+#[test]
+#[should_panic(expected = "the largest index 65535 is reserved")]
+fn narrow_index_overflow_potential() {
+    let mut ut: PotentialUnificationTable<SmallKey, i32> = PotentialUnificationTable::new();
+    for _ in 0..u16::MAX {
+        ut.new_key(());
+    }
+    ut.new_key(());
+}
+
+#[test]
+fn wide_index() {
+    all_modes! {
+        S for WideKey => {
+            let mut ut: UnificationTable<S, BySize> = UnificationTable::new();
+            let keys: Vec<WideKey> = (0..10).map(|_| ut.new_key(())).collect();
+            for i in 1..5 {
+                ut.union(keys[0], keys[i]);
+            }
+            assert_eq!(ut.find(keys[4]), keys[1]);
+            assert_eq!(ut.canonicalize().members(0), &keys[..5]);
+            assert_eq!(u64::from(keys[9]), 9);
+        }
+    }
+}
+
+#[test]
+fn snapshot_guard() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k0 = ut.new_key(());
+            let k1 = ut.new_key(());
+
+            {
+                let mut guard = ut.snapshot_guard();
+                let k2 = guard.new_key(());
+                guard.union(k0, k2);
+                guard.union(k1, k2);
+                assert_eq!(guard.vars_since_snapshot(), k2..UnitKey(3));
+                assert_eq!(guard.num_classes(), 1);
+            }
+            assert_eq!(ut.len(), 2);
+            assert!(!ut.unioned(k0, k1));
+
+            let mut guard = ut.snapshot_guard();
+            guard.union(k0, k1);
+            guard.rollback();
+            assert!(!ut.unioned(k0, k1));
+
+            let mut guard = ut.snapshot_guard();
+            guard.union(k0, k1);
+            {
+                let mut inner = guard.snapshot_guard();
+                inner.new_key(());
+            }
+            assert_eq!(guard.len(), 2);
+            guard.commit();
+            assert!(ut.unioned(k0, k1));
+            assert_eq!(ut.num_classes(), 1);
+        }
+    }
+}
+
+#[test]
+fn snapshot_guard_rolls_back_on_panic() {
+    use std::panic::{self, AssertUnwindSafe};
+
+    let mut ut: InPlaceUnificationTable<UnitKey> = UnificationTable::new();
+    let k0 = ut.new_key(());
+    let k1 = ut.new_key(());
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut guard = ut.snapshot_guard();
+        guard.union(k0, k1);
+        panic!("oops");
+    }));
+    assert!(result.is_err());
+    assert!(!ut.values.values.in_snapshot());
+    assert!(!ut.unioned(k0, k1));
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "snapshot dropped without being committed or rolled back")]
+fn dropped_snapshot_panics() {
+    let mut ut: InPlaceUnificationTable<UnitKey> = UnificationTable::new();
+    ut.new_key(());
+    let _ = ut.snapshot();
+}
+
+#[test]
+fn to_dot() {
+    let mut ut: InPlaceUnificationTable<IntKey> = UnificationTable::new();
+    let k0 = ut.new_key(None);
+    let k1 = ut.new_key(Some(3));
+    ut.unify_var_var(k0, k1).unwrap();
+    let snapshot = ut.snapshot();
+    ut.new_key(None);
+
+    assert_eq!(
+        ut.to_dot().to_string(),
+        "digraph \"IntKey\" {\n\
+         n0 [label=\"IntKey(0)\"];\n\
+         n1 [label=\"IntKey(1)\\nweight 1\\nSome(3)\", peripheries=2];\n\
+         n2 [label=\"IntKey(2)\\nweight 0\\nNone\", peripheries=2];\n\
+         n0 -> n1;\n\
+         }\n"
+    );
+    assert_eq!(
+        ut.to_dot()
+            .group_classes(true)
+            .highlight_since(&snapshot)
+            .to_string(),
+        "digraph \"IntKey\" {\n\
+         subgraph cluster_1 {\n\
+         n0 [label=\"IntKey(0)\"];\n\
+         n1 [label=\"IntKey(1)\\nweight 1\\nSome(3)\", peripheries=2];\n\
+         }\n\
+         subgraph cluster_2 {\n\
+         n2 [label=\"IntKey(2)\\nweight 0\\nNone\", peripheries=2, style=filled, fillcolor=yellow];\n\
+         }\n\
+         n0 -> n1;\n\
+         }\n"
+    );
+    ut.rollback_to(snapshot);
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn stats() {
+    use unify::UnifyStats;
+
+    let mut ut: InPlaceUnificationTable<UnitKey> = UnificationTable::new();
+    let k: Vec<UnitKey> = (0..4).map(|_| ut.new_key(())).collect();
+    ut.union(k[0], k[1]);
+    ut.union(k[2], k[3]);
+    ut.union(k[1], k[3]);
+    ut.union(k[1], k[3]);
+
+    let snapshot = ut.snapshot();
+    assert_eq!(ut.find(k[0]), k[3]);
+    assert_eq!(ut.find(k[0]), k[3]);
+    ut.rollback_to(snapshot);
+
+    let stats = ut.stats();
+    assert_eq!(
+        stats,
+        UnifyStats {
+            finds: 10,
+            total_path_length: 4,
+            max_path_length: 2,
+            compressions: 1,
+            unions: 3,
+        }
+    );
+    assert_eq!(stats.average_path_length(), 0.4);
+
+    let log_stats = ut.undo_log_stats();
+    assert_eq!(log_stats.peak_len, 1);
+    assert_eq!(log_stats.rollbacks, 1);
+    assert_eq!(log_stats.actions_rolled_back, 1);
+}
+
+#[test]
+fn check_invariants() {
+    all_modes! {
+        S for UnitKey => {
+            let mut ut: UnificationTable<S> = UnificationTable::new();
+            let k: Vec<UnitKey> = (0..6).map(|_| ut.new_key(())).collect();
+            assert_eq!(ut.check_invariants(), Ok(()));
+            ut.union(k[0], k[1]);
+            ut.union(k[2], k[3]);
+            ut.union(k[1], k[3]);
+            let snapshot = ut.snapshot();
+            ut.union(k[4], k[0]);
+            ut.find(k[0]);
+            assert_eq!(ut.check_invariants(), Ok(()));
+            ut.rollback_to(snapshot);
+            assert_eq!(ut.check_invariants(), Ok(()));
+        }
+    }
+}
+
+#[test]
+fn check_invariants_reports_corruption() {
+    use invariants::InvariantError;
+    use undo_log::{Snapshots, UndoLogs, VecLog};
+
+    fn table() -> InPlaceUnificationTable<UnitKey> {
+        let mut ut: InPlaceUnificationTable<UnitKey> = UnificationTable::new();
+        let k: Vec<UnitKey> = (0..3).map(|_| ut.new_key(())).collect();
+        ut.union(k[0], k[1]);
+        ut
+    }
+
+    let mut ut = table();
+    ut.values.values.get_mut(0).parent = UnitKey(7);
+    assert_eq!(
+        ut.check_invariants(),
+        Err(InvariantError::ParentOutOfRange { key: 0, parent: 7 })
+    );
+
+    let mut ut = table();
+    ut.values.values.get_mut(1).parent = UnitKey(0);
+    assert_eq!(ut.check_invariants(), Err(InvariantError::Cycle { key: 0 }));
+
+    let mut ut = table();
+    ut.values.values.get_mut(1).weight = 0;
+    assert_eq!(
+        ut.check_invariants(),
+        Err(InvariantError::WeightNotIncreasing { key: 0, parent: 1 })
+    );
+
+    let mut ut = table();
+    ut.values.values.get_mut(0).next = UnitKey(0);
+    assert_eq!(
+        ut.check_invariants(),
+        Err(InvariantError::BrokenMemberList { root: 1 })
+    );
+
+    let mut ut = table();
+    ut.num_classes = Some(3);
+    assert_eq!(
+        ut.check_invariants(),
+        Err(InvariantError::WrongClassCount {
+            counted: 3,
+            roots: 2
+        })
+    );
+
+    let mut log: VecLog<()> = VecLog::default();
+    let snapshot = log.start_snapshot();
+    log.push(());
+    assert_eq!(log.check_invariants(), Ok(()));
+    log.commit(snapshot);
+    log.push(());
+    assert_eq!(
+        log.check_invariants(),
+        Err(InvariantError::LogWithoutSnapshot { len: 1 })
+    );
+}
+
+#[test]
+fn sparse_keys_are_created_on_first_use() {
+    use unify::SparseUnificationTable;
+
+    let mut ut: SparseUnificationTable<IntKey> = UnificationTable::new();
+    ut.unify_var_value(0xdead_beef, Some(3)).unwrap();
+    ut.unify_var_var(0xdead_beef, 0x0bad_f00d).unwrap();
+    assert_eq!(ut.len(), 2);
+    assert_eq!(ut.num_classes(), 1);
+    assert_eq!(ut.probe_value(0x0bad_f00d), Some(3));
+    assert_eq!(ut.probe_value(7), None);
+    assert_eq!(ut.len(), 3);
+    assert_eq!(ut.roots().count(), 2);
+    assert_eq!(
+        ut.members(0xdead_beef).collect::<Vec<_>>(),
+        [IntKey(0xdead_beef), IntKey(0x0bad_f00d)]
+    );
+
+    // `new_key` skips the indices already in use.
+    let mut ut: SparseUnificationTable<UnitKey> = UnificationTable::new();
+    ut.find(1);
+    assert_eq!(ut.new_key(()), UnitKey(0));
+    assert_eq!(ut.new_key(()), UnitKey(2));
+    ut.find(4);
+    assert_eq!(ut.new_key(()), UnitKey(3));
+    assert_eq!(ut.new_key(()), UnitKey(5));
+    assert_eq!(ut.num_classes(), 6);
+
+    // Rolling back a snapshot rolls back the next index, too.
+    let snapshot = ut.snapshot();
+    assert_eq!(ut.new_key(()), UnitKey(6));
+    ut.rollback_to(snapshot);
+    assert_eq!(ut.new_key(()), UnitKey(6));
+}
+
+#[test]
+#[should_panic(expected = "the largest index 65535 is reserved")]
+fn sparse_largest_index_is_reserved() {
+    use unify::SparseUnificationTable;
+
+    let mut ut: SparseUnificationTable<SmallKey> = UnificationTable::new();
+    ut.find(SmallKey(u16::MAX - 1));
+    ut.find(SmallKey(u16::MAX));
+}
+
+#[test]
+fn sparse_rollback() {
+    use unify::SparseUnificationTable;
+
+    let mut ut: SparseUnificationTable<UnitKey> = UnificationTable::new();
+    let keys = [10, 1_000, 100_000, 10_000_000];
+    for pair in keys.windows(2) {
+        ut.union(pair[0], pair[1]);
+    }
+
+    let snapshot = ut.snapshot();
+    ut.union(keys[0], 55);
+    ut.find(keys[0]);
+    ut.reset_unifications(|_| ());
+    assert_eq!(ut.len(), 5);
+    assert_eq!(ut.num_classes(), 5);
+    ut.rollback_to(snapshot);
+
+    assert_eq!(ut.len(), 4);
+    assert_eq!(ut.num_classes(), 1);
+    assert_eq!(ut.to_dot().to_string().matches("->").count(), 3);
+    for &key in &keys {
+        assert!(ut.unioned_shared(keys[0], key));
+    }
+
+    // A key created inside a snapshot is removed by the rollback, and
+    // is created afresh by the next touch.
+    let snapshot = ut.snapshot();
+    ut.union(keys[0], 55);
+    assert_eq!(ut.vars_since_snapshot(&snapshot), UnitKey(55)..UnitKey(56));
+    ut.rollback_to(snapshot);
+    assert!(!ut.unioned(keys[0], 55));
+    assert_eq!(ut.len(), 5);
+}
+
+#[test]
+#[should_panic(expected = "no key with index 3")]
+fn sparse_shared_lookup_of_missing_key() {
+    use unify::SparseUnificationTable;
+
+    let ut: SparseUnificationTable<UnitKey> = UnificationTable::new();
+    ut.find_shared(3);
+}
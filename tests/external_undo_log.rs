@@ -1,26 +1,18 @@
 #[macro_use]
 extern crate log;
+#[macro_use]
 extern crate ena;
 
 use ena::{
     snapshot_vec as sv,
     undo_log::{Rollback, Snapshots, UndoLogs},
-    unify::{self as ut, EqUnifyValue, UnifyKey},
+    unify::{self as ut, EqUnifyValue},
 };
 
-#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
-struct IntKey(u32);
-
-impl UnifyKey for IntKey {
-    type Value = Option<IntKey>;
-    fn index(&self) -> u32 {
-        self.0
-    }
-    fn from_index(u: u32) -> IntKey {
-        IntKey(u)
-    }
-    fn tag() -> &'static str {
-        "IntKey"
+unify_key! {
+    struct IntKey {
+        value: Option<IntKey>,
+        tag: "IntKey",
     }
 }
 
@@ -80,7 +72,7 @@ struct TypeVariableTable<'a> {
 }
 
 impl TypeVariableTable<'_> {
-    fn new(&mut self, i: i32) -> IntKey {
+    fn new_var(&mut self, i: i32) -> IntKey {
         self.storage.values.with_log(&mut self.undo_log).push(i);
         self.storage
             .eq_relations
@@ -93,20 +85,12 @@ struct Snapshot {
     undo_len: usize,
 }
 
+#[derive(Default)]
 struct TypeVariableUndoLogs {
     logs: Vec<UndoLog>,
     num_open_snapshots: usize,
 }
 
-impl Default for TypeVariableUndoLogs {
-    fn default() -> Self {
-        Self {
-            logs: Default::default(),
-            num_open_snapshots: Default::default(),
-        }
-    }
-}
-
 impl<T> UndoLogs<T> for TypeVariableUndoLogs
 where
     UndoLog: From<T>,
@@ -193,10 +177,138 @@ fn external_undo_log() {
     let mut undo_log = TypeVariableUndoLogs::default();
 
     let snapshot = undo_log.start_snapshot();
-    storage.with_log(&mut undo_log).new(1);
-    storage.with_log(&mut undo_log).new(2);
+    storage.with_log(&mut undo_log).new_var(1);
+    storage.with_log(&mut undo_log).new_var(2);
     assert_eq!(storage.len(), 2);
 
     undo_log.rollback_to(|| &mut storage, snapshot);
     assert_eq!(storage.len(), 0);
 }
+
+/// Tests that the log-less storage can be queried through a shared reference
+#[test]
+fn probe_storage_without_log() {
+    let mut storage = TypeVariableStorage::default();
+    let mut undo_log = TypeVariableUndoLogs::default();
+
+    let a = storage.with_log(&mut undo_log).new_var(1);
+    let b = storage.with_log(&mut undo_log).new_var(2);
+    storage
+        .eq_relations
+        .with_log(&mut undo_log)
+        .unify_var_value(a, Some(b))
+        .unwrap();
+    storage
+        .eq_relations
+        .with_log(&mut undo_log)
+        .unify_var_var(a, b)
+        .unwrap();
+
+    let eq_relations = &storage.eq_relations;
+    assert!(eq_relations.unioned_shared(a, b));
+    assert_eq!(eq_relations.find_shared(a), eq_relations.find_shared(b));
+    assert_eq!(eq_relations.probe_value_shared(b), Some(b));
+}
+
+/// Tests that classes are counted correctly after rolling back an external undo log
+#[test]
+fn num_classes_with_external_log() {
+    let mut storage = TypeVariableStorage::default();
+    let mut undo_log = TypeVariableUndoLogs::default();
+
+    let a = storage.with_log(&mut undo_log).new_var(1);
+    let snapshot = undo_log.start_snapshot();
+    let b = storage.with_log(&mut undo_log).new_var(2);
+    storage
+        .eq_relations
+        .with_log(&mut undo_log)
+        .unify_var_var(a, b)
+        .unwrap();
+    assert_eq!(storage.eq_relations.num_classes(), 1);
+
+    undo_log.rollback_to(|| &mut storage, snapshot);
+    assert_eq!(storage.eq_relations.num_classes(), 1);
+    assert_eq!(storage.eq_relations.roots().collect::<Vec<_>>(), vec![a]);
+}
+
+#[derive(Default)]
+struct UnionCounter {
+    unions: usize,
+}
+
+impl ut::UnificationObserver<IntKey> for UnionCounter {
+    fn on_union(
+        &mut self,
+        _: IntKey,
+        _: IntKey,
+        _: (&Option<IntKey>, &Option<IntKey>),
+        _: &Option<IntKey>,
+    ) {
+        self.unions += 1;
+    }
+}
+
+#[derive(Default)]
+struct ObservedStorage {
+    eq_relations: ut::UnificationTableStorage<IntKey, ut::ByRank, UnionCounter>,
+}
+
+impl Rollback<UndoLog> for ObservedStorage {
+    fn reverse(&mut self, undo: UndoLog) {
+        match undo {
+            UndoLog::EqRelation(undo) => self.eq_relations.reverse(undo),
+            UndoLog::Values(_) => unreachable!(),
+        }
+    }
+}
+
+/// Tests that rolling back an external undo log does not notify the observer
+#[test]
+fn observer_with_external_log() {
+    let mut storage = ObservedStorage::default();
+    let mut undo_log = TypeVariableUndoLogs::default();
+
+    let a = storage.eq_relations.with_log(&mut undo_log).new_key(None);
+    let b = storage.eq_relations.with_log(&mut undo_log).new_key(None);
+    let snapshot = undo_log.start_snapshot();
+    storage
+        .eq_relations
+        .with_log(&mut undo_log)
+        .unify_var_var(a, b)
+        .unwrap();
+    assert_eq!(storage.eq_relations.observer().unions, 1);
+
+    undo_log.rollback_to(|| &mut storage, snapshot);
+    assert!(!storage.eq_relations.unioned_shared(a, b));
+    assert_eq!(storage.eq_relations.observer().unions, 1);
+}
+
+/// Tests the closure-based snapshot helpers of an external undo log
+#[test]
+fn probe_external_log() {
+    let mut storage = TypeVariableStorage::default();
+    let mut undo_log = TypeVariableUndoLogs::default();
+
+    let len = undo_log.probe(&mut storage, |undo_log, storage| {
+        storage.with_log(undo_log).new_var(1);
+        storage.len()
+    });
+    assert_eq!(len, 1);
+    assert_eq!(storage.len(), 0);
+
+    let result = undo_log.commit_if_ok(&mut storage, |undo_log, storage| {
+        let a = storage.with_log(&mut *undo_log).new_var(1);
+        let b = storage.with_log(&mut *undo_log).new_var(2);
+        storage
+            .eq_relations
+            .with_log(&mut *undo_log)
+            .unify_var_value(a, Some(b))?;
+        storage
+            .eq_relations
+            .with_log(undo_log)
+            .unify_var_value(a, Some(a))
+    });
+    assert!(result.is_err());
+    assert_eq!(storage.len(), 0);
+    assert_eq!(undo_log.num_open_snapshots, 0);
+}
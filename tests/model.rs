@@ -0,0 +1,107 @@
+#![cfg(feature = "testing")]
+
+#[macro_use]
+extern crate ena;
+
+use ena::snapshot_vec::SnapshotVecDelegate;
+use ena::testing::{Op, TableDriver, VecDriver, XorShift};
+use ena::unify::{ByIndex, BySize, EqUnifyValue, InPlace};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct IntValue(u8);
+
+impl EqUnifyValue for IntValue {}
+
+unify_key! {
+    struct IntKey {
+        value: Option<IntValue>,
+        index: u16,
+        tag: "IntKey",
+    }
+}
+
+fn value(rng: &mut XorShift) -> Option<IntValue> {
+    match rng.below(4) {
+        0 => Some(IntValue(rng.below(3) as u8)),
+        _ => None,
+    }
+}
+
+#[test]
+fn in_place_matches_model() {
+    for seed in 0..20 {
+        TableDriver::<InPlace<IntKey>>::new().run_random(seed, 500, value);
+        TableDriver::<InPlace<IntKey>, BySize>::new().run_random(seed, 500, value);
+        TableDriver::<InPlace<IntKey>, ByIndex>::new().run_random(seed, 500, value);
+    }
+}
+
+#[cfg(feature = "persistent")]
+#[test]
+fn persistent_matches_model() {
+    use ena::unify::Persistent;
+
+    for seed in 0..20 {
+        TableDriver::<Persistent<IntKey>>::new().run_random(seed, 500, value);
+    }
+}
+
+#[test]
+fn explicit_operations() {
+    let mut driver: TableDriver<InPlace<IntKey>> = TableDriver::new();
+    driver.apply(Op::NewKey(None));
+    driver.apply(Op::NewKey(Some(IntValue(1))));
+    driver.apply(Op::Snapshot);
+    driver.apply(Op::UnifyVarVar(0, 1));
+    driver.apply(Op::UnifyVarValue(0, Some(IntValue(2))));
+    assert_eq!(driver.model().probe_value(0), &Some(IntValue(1)));
+    driver.apply(Op::RollbackTo);
+    assert!(!driver.model().unioned(0, 1));
+    // Referring to a missing key or snapshot does nothing.
+    driver.apply(Op::UnifyVarVar(0, 5));
+    driver.apply(Op::Commit);
+    assert_eq!(driver.table().num_classes(), 2);
+}
+
+/// Adds its amount to every element; undone by subtracting it again.
+struct Counters;
+
+impl SnapshotVecDelegate for Counters {
+    type Value = i64;
+    type Undo = i64;
+
+    fn reverse(values: &mut Vec<i64>, amount: i64) {
+        for value in values {
+            *value -= amount;
+        }
+    }
+}
+
+#[test]
+fn snapshot_vec_matches_model() {
+    for seed in 0..20 {
+        VecDriver::<Counters>::new().run_random(
+            seed,
+            500,
+            |rng| rng.below(100) as i64,
+            |rng, vec, model| {
+                let amount = rng.below(10) as i64;
+                for (index, value) in model.iter_mut().enumerate() {
+                    *vec.get_mut(index) += amount;
+                    *value += amount;
+                }
+                vec.record(amount);
+            },
+        );
+    }
+}
+
+#[test]
+fn sparse_matches_model() {
+    use ena::unify::Sparse;
+
+    for seed in 0..20 {
+        TableDriver::<Sparse<IntKey>>::new().run_random(seed, 500, value);
+        TableDriver::<Sparse<IntKey>, BySize>::new().run_random(seed, 500, value);
+    }
+}
@@ -0,0 +1,171 @@
+#![cfg(feature = "serde")]
+
+#[macro_use]
+extern crate ena;
+extern crate serde;
+extern crate serde_json;
+
+use ena::{
+    snapshot_vec::SnapshotVec,
+    undo_log::{Snapshots, VecLog},
+    unify::{EqUnifyValue, InPlaceUnificationTable, PathCompression},
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+unify_key! {
+    struct IntKey {
+        value: Option<IntValue>,
+        tag: "IntKey",
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct IntValue(i32);
+
+impl EqUnifyValue for IntValue {}
+
+impl Serialize for IntValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for IntValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        i32::deserialize(deserializer).map(IntValue)
+    }
+}
+
+/// Tests that unions, values and settings survive a round trip
+#[test]
+fn round_trip_in_place() {
+    let mut table: InPlaceUnificationTable<IntKey> = InPlaceUnificationTable::new();
+    table.set_path_compression(PathCompression::Halving);
+    let keys: Vec<IntKey> = (0..4).map(|_| table.new_key(None)).collect();
+    table.unify_var_var(keys[0], keys[1]).unwrap();
+    table.unify_var_var(keys[1], keys[2]).unwrap();
+    table.unify_var_value(keys[2], Some(IntValue(3))).unwrap();
+
+    let json = serde_json::to_string(&table).unwrap();
+    let mut copy: InPlaceUnificationTable<IntKey> = serde_json::from_str(&json).unwrap();
+    assert_eq!(copy.len(), 4);
+    assert_eq!(copy.num_classes(), 2);
+    assert_eq!(copy.path_compression(), PathCompression::Halving);
+    assert!(copy.unioned(keys[0], keys[2]));
+    assert!(!copy.unioned(keys[0], keys[3]));
+    assert_eq!(copy.probe_value(keys[0]), Some(IntValue(3)));
+    assert_eq!(copy.members(keys[3]).count(), 1);
+
+    // The copy must be fully usable, including snapshots.
+    let snapshot = copy.snapshot();
+    copy.unify_var_var(keys[0], keys[3]).unwrap();
+    assert_eq!(copy.num_classes(), 1);
+    copy.rollback_to(snapshot);
+    assert_eq!(copy.num_classes(), 2);
+}
+
+#[cfg(feature = "persistent")]
+#[test]
+fn round_trip_persistent() {
+    use ena::unify::PersistentUnificationTable;
+
+    let mut table: PersistentUnificationTable<IntKey> = PersistentUnificationTable::new();
+    let a = table.new_key(Some(IntValue(1)));
+    let b = table.new_key(None);
+    table.unify_var_var(a, b).unwrap();
+
+    let json = serde_json::to_string(&table).unwrap();
+    let mut copy: PersistentUnificationTable<IntKey> = serde_json::from_str(&json).unwrap();
+    assert!(copy.unioned(a, b));
+    assert_eq!(copy.probe_value(b), Some(IntValue(1)));
+    assert_eq!(copy.num_classes(), 1);
+}
+
+/// Tests that open snapshots are reported instead of silently dropped
+#[test]
+fn open_snapshot_is_an_error() {
+    let mut table: InPlaceUnificationTable<IntKey> = InPlaceUnificationTable::new();
+    table.new_key(None);
+    let snapshot = table.snapshot();
+    assert!(serde_json::to_string(&table).is_err());
+    table.commit(snapshot);
+    assert!(serde_json::to_string(&table).is_ok());
+
+    let mut log: VecLog<()> = VecLog::default();
+    let snapshot = log.start_snapshot();
+    assert!(serde_json::to_string(&log).is_err());
+    log.commit(snapshot);
+    assert_eq!(serde_json::to_string(&log).unwrap(), "null");
+}
+
+#[test]
+fn round_trip_snapshot_vec() {
+    let mut vec: SnapshotVec<i32> = SnapshotVec::new();
+    vec.push(1);
+    vec.push(2);
+
+    let json = serde_json::to_string(&vec).unwrap();
+    assert_eq!(json, "[1,2]");
+    let copy: SnapshotVec<i32> = serde_json::from_str(&json).unwrap();
+    assert_eq!(copy.len(), 2);
+    assert_eq!(copy[1], 2);
+}
+
+/// Tests that keys pointing outside of the table are rejected
+#[test]
+fn out_of_range_key_is_rejected() {
+    let json = "[[[0,null,0,0],[2,null,0,1]],0]";
+    let result: Result<InPlaceUnificationTable<IntKey>, _> = serde_json::from_str(json);
+    assert!(result.is_err());
+
+    let json = "[[[0,null,1,1],[0,null,0,0]],0]";
+    let table: InPlaceUnificationTable<IntKey> = serde_json::from_str(json).unwrap();
+    assert_eq!(table.num_classes(), 1);
+}
+
+/// Tests that parents forming a cycle are rejected
+#[test]
+fn cyclic_parents_are_rejected() {
+    let json = "[[[1,null,0,1],[0,null,0,0]],0]";
+    let result: Result<InPlaceUnificationTable<IntKey>, _> = serde_json::from_str(json);
+    let error = result.unwrap_err().to_string();
+    assert!(error.contains("form a cycle"), "{}", error);
+}
+
+/// Tests that member lists that do not match the classes are rejected
+#[test]
+fn broken_member_list_is_rejected() {
+    let json = "[[[0,null,1,0],[0,null,0,1]],0]";
+    let result: Result<InPlaceUnificationTable<IntKey>, _> = serde_json::from_str(json);
+    let error = result.unwrap_err().to_string();
+    assert!(error.contains("member list"), "{}", error);
+}
+
+/// Tests that children at least as heavy as their parents are rejected
+#[test]
+fn heavy_child_is_rejected() {
+    let json = "[[[0,null,0,1],[0,null,0,0]],0]";
+    let result: Result<InPlaceUnificationTable<IntKey>, _> = serde_json::from_str(json);
+    let error = result.unwrap_err().to_string();
+    assert!(error.contains("weight"), "{}", error);
+}
+
+unify_key! {
+    struct SmallKey {
+        value: (),
+        index: u16,
+        tag: "SmallKey",
+    }
+}
+
+/// Tests that indices too large for the index type are rejected
+#[test]
+fn narrow_index_is_checked() {
+    let json = "[[[0,null,0,0],[70000,null,0,1]],0]";
+    let result: Result<InPlaceUnificationTable<SmallKey>, _> = serde_json::from_str(json);
+    assert!(result.is_err());
+
+    let json = "[[[1,null,0,1],[1,null,1,0]],0]";
+    let mut table: InPlaceUnificationTable<SmallKey> = serde_json::from_str(json).unwrap();
+    assert!(table.unioned(SmallKey(0), SmallKey(1)));
+}